@@ -0,0 +1,188 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Starting point for a new day, with `{day}` standing in for its number
+const TEMPLATE: &str = r#"use advent_of_code_2021::{report, Format};
+use std::fs::File;
+use std::io::read_to_string;
+use std::time::Instant;
+
+fn main() {
+    let mut f = File::open("day{day}.1.txt").unwrap();
+    let s = read_to_string(&mut f).unwrap();
+    let (format, _) = Format::from_args();
+
+    // no expected answers until they're accepted, so the parts are reported as unknown
+    let start = Instant::now();
+    let answer = part_one(&s);
+    report(format, ({day}, 1), answer, None, start.elapsed(), || {
+        println!("Part one: {}", answer)
+    });
+
+    let start = Instant::now();
+    let answer = part_two(&s);
+    report(format, ({day}, 2), answer, None, start.elapsed(), || {
+        println!("Part two: {}", answer)
+    });
+}
+
+fn part_one(_input: &str) -> u64 {
+    0
+}
+
+fn part_two(_input: &str) -> u64 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "no example answer yet"]
+    fn part_one_works() {
+        let s = include_str!("day{day}.test.txt");
+        assert_eq!(part_one(s), 0);
+    }
+
+    #[test]
+    #[ignore = "no example answer yet"]
+    fn part_two_works() {
+        let s = include_str!("day{day}.test.txt");
+        assert_eq!(part_two(s), 0);
+    }
+}
+"#;
+
+fn main() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("new") => {
+            let day = args
+                .next()
+                .expect("new requires a day")
+                .parse()
+                .expect("day must be a number");
+            match new_day(root, day) {
+                Ok(created) => created
+                    .iter()
+                    .for_each(|p| println!("Created {}", p.display())),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some("run") => {
+            let mut format = "text".to_string();
+            let mut wanted = vec![];
+            while let Some(arg) = args.next() {
+                match arg.as_ref() {
+                    "--format" => format = args.next().expect("--format requires a format"),
+                    day => wanted.push(day.parse::<u32>().expect("day must be a number")),
+                }
+            }
+            for day in days(root).unwrap() {
+                if !wanted.is_empty() && !wanted.contains(&day) {
+                    continue;
+                }
+                let status = Command::new("cargo")
+                    .current_dir(root)
+                    .args(["run", "--quiet", "--release", "--bin"])
+                    .arg(format!("day{}", day))
+                    .args(["--", "--format", &format])
+                    .status()
+                    .unwrap();
+                if !status.success() {
+                    std::process::exit(status.code().unwrap_or(1));
+                }
+            }
+        }
+        _ => {
+            eprintln!("Usage: aoc new <day> | aoc run [--format text|json] [day...]");
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Days the runner knows about: every `src/bin/day<N>.rs`, in order
+fn days(root: &Path) -> io::Result<Vec<u32>> {
+    let mut days = vec![];
+    for entry in fs::read_dir(root.join("src/bin"))? {
+        let name = entry?.file_name();
+        let name = name.to_string_lossy();
+        if let Some(day) = name
+            .strip_prefix("day")
+            .and_then(|n| n.strip_suffix(".rs"))
+            .and_then(|n| n.parse().ok())
+        {
+            days.push(day);
+        }
+    }
+    days.sort_unstable();
+    Ok(days)
+}
+
+/// Create the binary, input stub and test fixture for a day. Nothing is written if any of
+/// them already exists. Creating the binary is what registers the day with `aoc run`.
+fn new_day(root: &Path, day: u32) -> io::Result<Vec<PathBuf>> {
+    let files = [
+        (
+            root.join(format!("src/bin/day{}.rs", day)),
+            TEMPLATE.replace("{day}", &day.to_string()),
+        ),
+        (
+            root.join(format!("src/bin/day{}.test.txt", day)),
+            String::new(),
+        ),
+        (root.join(format!("day{}.1.txt", day)), String::new()),
+    ];
+    if let Some((path, _)) = files.iter().find(|(path, _)| path.exists()) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", path.display()),
+        ));
+    }
+    for (path, contents) in &files {
+        let mut f = OpenOptions::new().write(true).create_new(true).open(path)?;
+        f.write_all(contents.as_bytes())?;
+    }
+    Ok(files.into_iter().map(|(path, _)| path).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_day_works() {
+        let root = std::env::temp_dir().join(format!("aoc-new-{}", std::process::id()));
+        fs::create_dir_all(root.join("src/bin")).unwrap();
+        fs::write(root.join("src/bin/day3.rs"), "").unwrap();
+
+        let created = new_day(&root, 12).unwrap();
+        assert_eq!(created.len(), 3);
+        let bin = fs::read_to_string(root.join("src/bin/day12.rs")).unwrap();
+        assert!(bin.contains("File::open(\"day12.1.txt\")"));
+        assert!(bin.contains("report(format, (12, 2), answer, None"));
+        assert!(bin.contains("include_str!(\"day12.test.txt\")"));
+        assert!(!bin.contains("#![feature"));
+        assert_eq!(days(&root).unwrap(), vec![3, 12]);
+
+        // one existing file stops anything from being written
+        fs::remove_file(root.join("day12.1.txt")).unwrap();
+        fs::write(root.join("src/bin/day12.rs"), "keep").unwrap();
+        let e = new_day(&root, 12).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(
+            fs::read_to_string(root.join("src/bin/day12.rs")).unwrap(),
+            "keep"
+        );
+        assert!(!root.join("day12.1.txt").exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,51 +1,392 @@
 #![feature(io_read_to_string)]
 
+use advent_of_code_2021::{report, Format};
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::fmt;
 use std::fs::File;
-use std::io::read_to_string;
+use std::io::{read_to_string, BufRead};
+use std::time::Instant;
 
 fn main() {
     let mut f = File::open("day1.1.txt").unwrap();
     let is = read_to_string(&mut f).unwrap();
-    let input: Vec<i32> = is
-        .split_whitespace()
-        .map(|ns| ns.parse().unwrap())
-        .collect();
+    let parse =
+        |s: &str| -> Vec<i32> { s.split_whitespace().map(|ns| ns.parse().unwrap()).collect() };
+    let (format, args) = Format::from_args_allowing(&["--stats"]);
+    let start = Instant::now();
+    let input = parse(&is);
     let answer = count_increases_2(&input);
-    assert_eq!(answer, 1195);
-    println!("Part 1 answer: {}", answer);
+    report(format, (1, 1), answer, Some(1195), start.elapsed(), || {
+        println!("Part 1 answer: {}", answer)
+    });
 
+    let start = Instant::now();
+    let input = parse(&is);
     let answer = count_increases_3(&input);
-    assert_eq!(answer, 1235);
-    println!("Part 2 answer: {}", answer);
+    report(format, (1, 2), answer, Some(1235), start.elapsed(), || {
+        println!("Part 2 answer: {}", answer)
+    });
+
+    let mut options = TrendOptions::default();
+    let mut every = 1000;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| -> String {
+            args.next()
+                .unwrap_or_else(|| panic!("{} requires a value", name))
+        };
+        match arg.as_ref() {
+            // settings used by the following --trend
+            "--window" => options.window = value("--window").parse().unwrap(),
+            "--dead-band" => options.dead_band = value("--dead-band").parse().unwrap(),
+            "--median" => options.smoothing = Smoothing::Median(value("--median").parse().unwrap()),
+            "--ema" => options.smoothing = Smoothing::Exponential(value("--ema").parse().unwrap()),
+            "--trend" => println!("{}", trend(&input, &options)),
+            // how often the following --stream reports
+            "--every" => {
+                every = value("--every").parse().unwrap();
+                assert!(every > 0, "--every must be at least 1");
+            }
+            "--stream" => {
+                let windows: Vec<usize> = args
+                    .next()
+                    .expect("--stream requires comma separated window sizes")
+                    .split(',')
+                    .map(|w| w.parse().unwrap())
+                    .collect();
+                let readings = std::io::stdin()
+                    .lock()
+                    .lines()
+                    .map(|l| l.unwrap())
+                    .filter(|l| !l.trim().is_empty())
+                    .map(|l| l.trim().parse().unwrap());
+                // a feed may never end, so report as it goes
+                let done = sweep(readings, &windows, |sweep| {
+                    if sweep.readings.is_multiple_of(every) {
+                        println!("{}", sweep.report());
+                    }
+                });
+                if !done.readings.is_multiple_of(every) {
+                    println!("{}", done.report());
+                }
+            }
+            "--stats" => {
+                let window = args
+                    .next()
+                    .expect("--stats requires a window size")
+                    .parse()
+                    .unwrap();
+                let stats = statistics(&input, window);
+                match format {
+                    Format::Text => println!("{}", stats),
+                    Format::Json => println!("{}", stats.to_json()),
+                }
+            }
+            _ => panic!("Unknown argument: {}", arg),
+        }
+    }
 }
 
 fn count_increases_2(input: &[i32]) -> i32 {
-    input
-        .windows(2)
-        .map(|slice| {
-            if slice[0] < slice[1] {
-                return 1;
-            }
-            0
-        })
-        .reduce(|a, b| a + b)
-        .unwrap()
+    count_increases(input, 1)
 }
 
 fn count_increases_3(input: &[i32]) -> i32 {
-    input
-        .windows(3)
-        .map(|slice| slice.iter().sum())
-        .collect::<Vec<i32>>()
-        .windows(2)
-        .map(|slice| {
-            if slice[0] < slice[1] {
-                return 1;
+    count_increases(input, 3)
+}
+
+/// How many times the sum of `window` consecutive readings is larger than the one before
+fn count_increases(input: &[i32], window: usize) -> i32 {
+    if window == 0 || input.len() <= window {
+        return 0;
+    }
+    let mut sum: i32 = input[..window].iter().sum();
+    let mut count = 0;
+    for (entering, leaving) in input[window..].iter().zip(input) {
+        let next = sum + entering - leaving;
+        if next > sum {
+            count += 1;
+        }
+        sum = next;
+    }
+    count
+}
+
+/// Counts increases for several window sizes at once as readings arrive, remembering only
+/// as many readings as the largest window
+struct SonarSweep {
+    windows: Vec<usize>,
+    largest: usize,
+    recent: VecDeque<i32>,
+    sums: Vec<i32>,
+    counts: Vec<u64>,
+    readings: u64,
+}
+
+impl SonarSweep {
+    fn new(windows: &[usize]) -> Self {
+        let largest = windows.iter().copied().max().unwrap_or(0);
+        SonarSweep {
+            windows: windows.to_vec(),
+            largest,
+            recent: VecDeque::with_capacity(largest + 1),
+            sums: vec![0; windows.len()],
+            counts: vec![0; windows.len()],
+            readings: 0,
+        }
+    }
+
+    fn push(&mut self, reading: i32) {
+        self.readings += 1;
+        for (i, &w) in self.windows.iter().enumerate() {
+            if w == 0 {
+                continue;
             }
-            0
-        })
-        .reduce(|a, b| a + b)
-        .unwrap()
+            if self.recent.len() >= w {
+                let next = self.sums[i] + reading - self.recent[self.recent.len() - w];
+                if next > self.sums[i] {
+                    self.counts[i] += 1;
+                }
+                self.sums[i] = next;
+            } else {
+                self.sums[i] += reading;
+            }
+        }
+        self.recent.push_back(reading);
+        if self.recent.len() > self.largest {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Increases seen so far, in the order the windows were given
+    fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Summary of the counts so far, one window after another
+    fn report(&self) -> String {
+        let counts: Vec<_> = self
+            .windows
+            .iter()
+            .zip(self.counts())
+            .map(|(w, count)| format!("window {}: {} increases", w, count))
+            .collect();
+        format!("After {} readings: {}", self.readings, counts.join(", "))
+    }
+}
+
+/// Sweep through readings, calling `progress` after each one
+fn sweep<I: IntoIterator<Item = i32>>(
+    readings: I,
+    windows: &[usize],
+    mut progress: impl FnMut(&SonarSweep),
+) -> SonarSweep {
+    let mut sweep = SonarSweep::new(windows);
+    for reading in readings {
+        sweep.push(reading);
+        progress(&sweep);
+    }
+    sweep
+}
+
+/// Everything that happens between consecutive window sums. Positions are the index of the
+/// later window of each pair, counting windows from zero.
+#[derive(Debug, Default, PartialEq)]
+struct SweepStats {
+    window: usize,
+    increases: Vec<usize>,
+    decreases: Vec<usize>,
+    plateaus: Vec<usize>,
+    /// Position where the longest run of consecutive increases starts, and its length
+    longest_run: Option<(usize, usize)>,
+    /// Position and size of the biggest change either way
+    largest_jump: Option<(usize, i32)>,
+}
+
+fn statistics(input: &[i32], window: usize) -> SweepStats {
+    let mut stats = SweepStats {
+        window,
+        ..SweepStats::default()
+    };
+    if window == 0 || input.len() <= window {
+        return stats;
+    }
+    // the sums of neighbouring windows differ by the readings entering and leaving them
+    let mut run = 0;
+    for (i, (entering, leaving)) in input[window..].iter().zip(input).enumerate() {
+        let pos = i + 1;
+        let jump = entering - leaving;
+        match jump.cmp(&0) {
+            Ordering::Greater => {
+                stats.increases.push(pos);
+                run += 1;
+                if stats.longest_run.is_none_or(|(_, len)| run > len) {
+                    stats.longest_run = Some((pos + 1 - run, run));
+                }
+            }
+            Ordering::Less => stats.decreases.push(pos),
+            Ordering::Equal => stats.plateaus.push(pos),
+        }
+        if jump <= 0 {
+            run = 0;
+        }
+        if stats.largest_jump.is_none_or(|(_, j)| jump.abs() > j.abs()) {
+            stats.largest_jump = Some((pos, jump));
+        }
+    }
+    stats
+}
+
+impl SweepStats {
+    fn to_json(&self) -> String {
+        let list = |v: &[usize]| {
+            let items: Vec<_> = v.iter().map(|p| p.to_string()).collect();
+            format!("[{}]", items.join(","))
+        };
+        let run = match self.longest_run {
+            Some((start, length)) => format!("{{\"start\":{},\"length\":{}}}", start, length),
+            None => "null".to_string(),
+        };
+        let jump = match self.largest_jump {
+            Some((position, size)) => {
+                format!("{{\"position\":{},\"size\":{}}}", position, size)
+            }
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"window\":{},\"increases\":{},\"decreases\":{},\"plateaus\":{},\"longest_run\":{},\"largest_jump\":{}}}",
+            self.window,
+            list(&self.increases),
+            list(&self.decreases),
+            list(&self.plateaus),
+            run,
+            jump
+        )
+    }
+}
+
+impl fmt::Display for SweepStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Window size: {}", self.window)?;
+        writeln!(f, "Increases:   {}", self.increases.len())?;
+        writeln!(f, "Decreases:   {}", self.decreases.len())?;
+        writeln!(f, "Plateaus:    {}", self.plateaus.len())?;
+        match self.longest_run {
+            Some((start, len)) => {
+                writeln!(f, "Longest run: {} increases from window {}", len, start)?
+            }
+            None => writeln!(f, "Longest run: none")?,
+        }
+        match self.largest_jump {
+            Some((pos, jump)) => write!(f, "Largest jump: {:+} at window {}", jump, pos),
+            None => write!(f, "Largest jump: none"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Smoothing {
+    None,
+    /// Median of the last n readings; the first n - 1 readings produce nothing
+    Median(usize),
+    /// Exponential moving average giving the newest reading this weight
+    Exponential(f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TrendOptions {
+    window: usize,
+    /// Changes smaller than this are treated as noise
+    dead_band: f64,
+    smoothing: Smoothing,
+}
+
+impl Default for TrendOptions {
+    /// Plain puzzle counting
+    fn default() -> Self {
+        TrendOptions {
+            window: 1,
+            dead_band: 0.0,
+            smoothing: Smoothing::None,
+        }
+    }
+}
+
+/// Significant changes between consecutive windows, and the windows where the direction flips
+#[derive(Debug, Default, PartialEq)]
+struct Trend {
+    increases: usize,
+    decreases: usize,
+    reversals: Vec<usize>,
+}
+
+impl fmt::Display for Trend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Increases: {}, decreases: {}, reversals: {}",
+            self.increases,
+            self.decreases,
+            self.reversals.len()
+        )
+    }
+}
+
+fn smooth(input: &[i32], smoothing: Smoothing) -> Vec<f64> {
+    let readings = input.iter().map(|&r| r as f64);
+    match smoothing {
+        Smoothing::None => readings.collect(),
+        Smoothing::Median(0) => vec![],
+        Smoothing::Median(n) => input
+            .windows(n)
+            .map(|w| {
+                let mut sorted = w.to_vec();
+                sorted.sort_unstable();
+                if n % 2 == 1 {
+                    sorted[n / 2] as f64
+                } else {
+                    (sorted[n / 2 - 1] + sorted[n / 2]) as f64 / 2.0
+                }
+            })
+            .collect(),
+        Smoothing::Exponential(weight) => readings
+            .scan(None, |average: &mut Option<f64>, r| {
+                let next = average.map_or(r, |a| weight * r + (1.0 - weight) * a);
+                *average = Some(next);
+                Some(next)
+            })
+            .collect(),
+    }
+}
+
+/// Count increases like the puzzle does, but after smoothing the readings and ignoring
+/// changes inside the dead band. Positions count windows from zero as in [SweepStats].
+fn trend(input: &[i32], options: &TrendOptions) -> Trend {
+    let readings = smooth(input, options.smoothing);
+    let mut trend = Trend::default();
+    if options.window == 0 || readings.len() <= options.window {
+        return trend;
+    }
+    let mut rising = None;
+    let pairs = readings[options.window..].iter().zip(&readings);
+    for (i, (entering, leaving)) in pairs.enumerate() {
+        let change = entering - leaving;
+        if change == 0.0 || change.abs() < options.dead_band {
+            continue;
+        }
+        let up = change > 0.0;
+        if up {
+            trend.increases += 1;
+        } else {
+            trend.decreases += 1;
+        }
+        if rising.is_some_and(|r| r != up) {
+            trend.reversals.push(i + 1);
+        }
+        rising = Some(up);
+    }
+    trend
 }
 
 #[test]
@@ -59,3 +400,107 @@ fn window_3() {
     let input = vec![199, 200, 208, 210, 200, 207, 240, 269, 260, 263];
     assert_eq!(count_increases_3(&input), 5);
 }
+
+#[test]
+fn any_window() {
+    let input = vec![199, 200, 208, 210, 200, 207, 240, 269, 260, 263];
+    assert_eq!(count_increases(&input, 1), 7);
+    assert_eq!(count_increases(&input, 3), 5);
+    assert_eq!(count_increases(&input, 9), 1);
+    assert_eq!(count_increases(&input, 10), 0);
+    assert_eq!(count_increases(&input, 0), 0);
+    assert_eq!(count_increases_2(&[]), 0);
+    assert_eq!(count_increases_3(&[1, 2]), 0);
+}
+
+#[test]
+fn sweep_works() {
+    let input = vec![199, 200, 208, 210, 200, 207, 240, 269, 260, 263];
+    let windows = [1, 3, 9, 10, 0];
+    let expected: Vec<_> = windows
+        .iter()
+        .map(|&w| count_increases(&input, w) as u64)
+        .collect();
+    assert_eq!(
+        sweep(input.iter().copied(), &windows, |_| {}).counts(),
+        expected
+    );
+    assert_eq!(sweep(std::iter::empty(), &[1, 3], |_| {}).counts(), [0, 0]);
+
+    // an endless feed only keeps the largest window around
+    let mut sweep = SonarSweep::new(&[1, 3]);
+    (0..100_000).for_each(|r| sweep.push(r % 7));
+    assert!(sweep.recent.len() <= 3);
+    let readings: Vec<_> = (0..100_000).map(|r| r % 7).collect();
+    assert_eq!(sweep.counts()[0], count_increases(&readings, 1) as u64);
+    assert_eq!(
+        sweep.report(),
+        format!(
+            "After 100000 readings: window 1: {} increases, window 3: {} increases",
+            sweep.counts()[0],
+            sweep.counts()[1]
+        )
+    );
+}
+
+#[test]
+fn statistics_works() {
+    let input = vec![199, 200, 208, 210, 200, 207, 240, 269, 260, 263];
+    let stats = statistics(&input, 1);
+    assert_eq!(stats.increases, vec![1, 2, 3, 5, 6, 7, 9]);
+    assert_eq!(stats.decreases, vec![4, 8]);
+    assert!(stats.plateaus.is_empty());
+    assert_eq!(stats.longest_run, Some((1, 3)));
+    assert_eq!(stats.largest_jump, Some((6, 33)));
+
+    let stats = statistics(&input, 3);
+    assert_eq!(stats.increases.len(), count_increases(&input, 3) as usize);
+    assert_eq!(stats.plateaus, vec![2]);
+    assert_eq!(
+        stats.to_json(),
+        "{\"window\":3,\"increases\":[1,4,5,6,7],\"decreases\":[3],\"plateaus\":[2],\"longest_run\":{\"start\":4,\"length\":4},\"largest_jump\":{\"position\":5,\"size\":69}}"
+    );
+
+    assert_eq!(
+        statistics(&[1], 1).to_string(),
+        "Window size: 1\nIncreases:   0\nDecreases:   0\nPlateaus:    0\nLongest run: none\nLargest jump: none"
+    );
+}
+
+#[test]
+fn trend_works() {
+    let input = vec![199, 200, 208, 210, 200, 207, 240, 269, 260, 263];
+    let plain = trend(&input, &TrendOptions::default());
+    assert_eq!(plain.increases, count_increases_2(&input) as usize);
+    assert_eq!(plain.reversals, vec![4, 5, 8, 9]);
+    let options = TrendOptions {
+        window: 3,
+        ..TrendOptions::default()
+    };
+    assert_eq!(trend(&input, &options).increases, 5);
+
+    let options = TrendOptions {
+        dead_band: 5.0,
+        ..TrendOptions::default()
+    };
+    let banded = trend(&input, &options);
+    assert_eq!((banded.increases, banded.decreases), (4, 2));
+
+    let noisy = vec![10, 11, 10, 11, 10, 20, 21, 20, 21, 20];
+    let options = TrendOptions {
+        smoothing: Smoothing::Median(3),
+        ..TrendOptions::default()
+    };
+    assert_eq!(
+        smooth(&noisy, options.smoothing),
+        vec![10.0, 11.0, 10.0, 11.0, 20.0, 20.0, 21.0, 20.0]
+    );
+    let options = TrendOptions {
+        smoothing: Smoothing::Exponential(0.5),
+        dead_band: 1.0,
+        ..TrendOptions::default()
+    };
+    let smoothed = trend(&noisy, &options);
+    assert_eq!(smoothed.reversals, vec![]);
+    assert_eq!(smoothed.increases, 2);
+}
@@ -1,92 +1,363 @@
 #![feature(io_read_to_string)]
+use advent_of_code_2021::{report, Format};
 use lazy_static::lazy_static;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::fs::File;
-use std::io::read_to_string;
+use std::io::{read_to_string, BufReader, Read};
+use std::time::Instant;
 
 fn main() {
     let mut f = File::open("day10.1.txt").unwrap();
     let s = read_to_string(&mut f).unwrap();
-    let score = syntax_error_score(&s, &ERR_POINT_TABLE);
-    assert_eq!(score, 367059);
-    println!("Part one: {}", score);
+    let (format, args) = Format::from_args();
+    let start = Instant::now();
+    let score = syntax_error_score(&s, &DEFAULT_GRAMMAR);
+    report(
+        format,
+        (10, 1),
+        score,
+        Some(367059),
+        start.elapsed(),
+        || println!("Part one: {}", score),
+    );
 
-    let score = completion_score(&s, &COMPLETION_POINT_TABLE);
-    assert_eq!(score, 1952146692);
-    println!("Part two: {}", score);
+    let start = Instant::now();
+    let score = completion_score(&s, &DEFAULT_GRAMMAR);
+    report(
+        format,
+        (10, 2),
+        score,
+        Some(1952146692),
+        start.elapsed(),
+        || println!("Part two: {}", score),
+    );
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--grammar" => {
+                let grammar = args.next().expect("--grammar requires a grammar file");
+                let input = args.next().expect("--grammar requires an input file");
+                let grammar: BracketGrammar = read_to_string(&mut File::open(grammar).unwrap())
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                let s = read_to_string(&mut File::open(input).unwrap()).unwrap();
+                println!("Syntax error score: {}", syntax_error_score(&s, &grammar));
+                println!("Completion score: {}", completion_score(&s, &grammar));
+            }
+            "--diagnostics" => {
+                let path = args.next().expect("--diagnostics requires an input file");
+                let s = read_to_string(&mut File::open(&path).unwrap()).unwrap();
+                for (i, line) in s.lines().enumerate() {
+                    let status = check_line(line, &DEFAULT_GRAMMAR);
+                    if let Some(d) = diagnostic(&path, i + 1, line, &status) {
+                        println!("{}", d);
+                    }
+                }
+            }
+            "--repair" => {
+                let path = args.next().expect("--repair requires an input file");
+                let s = read_to_string(&mut File::open(&path).unwrap()).unwrap();
+                let costs = RepairCosts::uniform(&DEFAULT_GRAMMAR, 1);
+                for (i, line) in s.lines().enumerate() {
+                    if check_line(line, &DEFAULT_GRAMMAR) == LineStatus::Ok {
+                        continue;
+                    }
+                    let repair = repair_line(line, &DEFAULT_GRAMMAR, &costs);
+                    println!("{}:{}: {} (cost {})", path, i + 1, repair.line, repair.cost);
+                    for edit in repair.edits {
+                        println!("    {:?}", edit);
+                    }
+                }
+            }
+            "--stream" | "--stream-document" => {
+                let path = args.next().expect("--stream requires an input file");
+                let bytes = BufReader::new(File::open(&path).unwrap())
+                    .bytes()
+                    .map(|b| b.expect("Failed to read input"));
+                let document = arg == "--stream-document";
+                let mut problems = 0;
+                for status in StreamChecker::new(bytes, &DEFAULT_GRAMMAR, document) {
+                    match status {
+                        StreamStatus::Ok => {}
+                        StreamStatus::Corrupted {
+                            at,
+                            expected,
+                            found,
+                        } => {
+                            problems += 1;
+                            println!(
+                                "{}:{}:{} (byte {}): found `{}`, expected {:?}",
+                                path,
+                                at.line + 1,
+                                at.column + 1,
+                                at.offset,
+                                found,
+                                expected
+                            );
+                        }
+                        StreamStatus::Incomplete { at, missing } => {
+                            problems += 1;
+                            println!(
+                                "{}:{}:{} (byte {}): missing `{}`",
+                                path,
+                                at.line + 1,
+                                at.column + 1,
+                                at.offset,
+                                missing
+                            );
+                        }
+                    }
+                }
+                println!("{} problems", problems);
+            }
+            "--stats" => {
+                let path = args.next().expect("--stats requires an input file");
+                let s = read_to_string(&mut File::open(&path).unwrap()).unwrap();
+                print!("{}", input_stats(&s, &DEFAULT_GRAMMAR));
+            }
+            _ => panic!("Unknown argument: {}", arg),
+        }
+    }
 }
 
 lazy_static! {
-    static ref ERR_POINT_TABLE: HashMap<char, u64> = {
-        let mut map = HashMap::new();
-        map.insert(')', 3);
-        map.insert(']', 57);
-        map.insert('}', 1197);
-        map.insert('>', 25137);
-        map
-    };
+    static ref DEFAULT_GRAMMAR: BracketGrammar = BracketGrammar::new(&[
+        ("(", ")", 3, 1),
+        ("[", "]", 57, 2),
+        ("{", "}", 1197, 3),
+        ("<", ">", 25137, 4),
+    ]);
 }
 
-lazy_static! {
-    static ref COMPLETION_POINT_TABLE: HashMap<char, u64> = {
-        let mut map = HashMap::new();
-        map.insert(')', 1);
-        map.insert(']', 2);
-        map.insert('}', 3);
-        map.insert('>', 4);
-        map
-    };
+/// A delimiter recognised by a grammar, identified by the index of its pair
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Token {
+    Open(usize),
+    Close(usize),
 }
 
-const OPENING_CHARS: [char; 4] = ['(', '[', '{', '<'];
-const CLOSING_CHARS: [char; 4] = [')', ']', '}', '>'];
+/// Set of opening/closing delimiter pairs along with the points awarded for each closing
+/// delimiter. Delimiters may be several characters long, e.g. `begin`/`end` or `/*`/`*/`.
+#[derive(Debug)]
+struct BracketGrammar {
+    pairs: Vec<(String, String)>,
+    error_points: HashMap<String, u64>,
+    completion_points: HashMap<String, u64>,
+}
 
-lazy_static! {
-    static ref MATCH: HashMap<char, char> = {
-        let mut map = HashMap::new();
-        for (a, b) in OPENING_CHARS.into_iter().zip(CLOSING_CHARS.into_iter()) {
-            map.insert(a, b);
+impl BracketGrammar {
+    /// Build a grammar from `(open, close, error points, completion points)` entries
+    fn new(delimiters: &[(&str, &str, u64, u64)]) -> Self {
+        let mut pairs = vec![];
+        let mut error_points = HashMap::new();
+        let mut completion_points = HashMap::new();
+        for &(open, close, error, completion) in delimiters {
+            // an empty delimiter would match everywhere without consuming anything
+            assert!(
+                !open.is_empty() && !close.is_empty(),
+                "Delimiters can't be empty"
+            );
+            // a delimiter that both opens and closes can't be told apart
+            assert!(open != close, "`{}` can't both open and close a pair", open);
+            pairs.push((open.to_string(), close.to_string()));
+            error_points.insert(close.to_string(), error);
+            completion_points.insert(close.to_string(), completion);
         }
+        Self {
+            pairs,
+            error_points,
+            completion_points,
+        }
+    }
 
-        map
-    };
+    /// Split a line into its delimiters along with their byte offsets. The longest delimiter
+    /// starting at a position wins, and anything that isn't a delimiter is skipped.
+    fn tokens<'a>(&'a self, line: &'a str) -> impl Iterator<Item = (usize, Token)> + 'a {
+        let delimiters = self.delimiters();
+        let mut pos = 0;
+        std::iter::from_fn(move || {
+            while pos < line.len() {
+                let rest = &line[pos..];
+                let found = delimiters
+                    .iter()
+                    .find(|(delim, _)| rest.starts_with(delim))
+                    .copied();
+
+                let start = pos;
+                match found {
+                    Some((delim, token)) => {
+                        pos += delim.len();
+                        return Some((start, token));
+                    }
+                    None => pos += rest.chars().next().unwrap().len_utf8(),
+                }
+            }
+            None
+        })
+    }
+
+    /// Every delimiter in the order they are tried, longest first. A delimiter used more than
+    /// once means whichever use comes first: pairs in order, opening before closing.
+    fn delimiters(&self) -> Vec<(&str, Token)> {
+        let mut delimiters: Vec<_> = self
+            .pairs
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (open, close))| {
+                [
+                    (open.as_str(), Token::Open(i)),
+                    (close.as_str(), Token::Close(i)),
+                ]
+            })
+            .collect();
+        // stable, so equal lengths keep the pair order
+        delimiters.sort_by_key(|(delim, _)| std::cmp::Reverse(delim.len()));
+        delimiters
+    }
+
+    fn opening(&self, pair: usize) -> &str {
+        &self.pairs[pair].0
+    }
+
+    fn closing(&self, pair: usize) -> &str {
+        &self.pairs[pair].1
+    }
+
+    fn delimiter(&self, token: Token) -> &str {
+        match token {
+            Token::Open(pair) => self.opening(pair),
+            Token::Close(pair) => self.closing(pair),
+        }
+    }
 }
 
-fn completion_score(s: &str, point_table: &HashMap<char, u64>) -> u64 {
-    let mut v: Vec<u64> = s
-        .lines()
-        .filter_map(|line| {
-            let mut stack = vec![];
-            for c in line.chars() {
-                if OPENING_CHARS.contains(&c) {
-                    stack.push(c);
-                    continue;
+/// Problem with a line of a grammar file, counting lines from one
+#[derive(Debug, Eq, PartialEq)]
+struct GrammarError {
+    line: usize,
+    message: String,
+}
+
+impl std::fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::str::FromStr for BracketGrammar {
+    type Err = GrammarError;
+    /// Parse one `open close error_points completion_points` entry per line
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries = vec![];
+        for (i, line) in s.lines().enumerate() {
+            let v: Vec<_> = line.split_whitespace().collect();
+            let error = |message: String| GrammarError {
+                line: i + 1,
+                message,
+            };
+            match v[..] {
+                [] => continue,
+                [open, close, _, _] if open == close => {
+                    return Err(error(format!(
+                        "`{}` can't both open and close a pair",
+                        open
+                    )))
                 }
-                // Otherwise closing character must match the last character
-                if let Some(&opening) = stack.last() {
-                    // We have matching characters.
-                    if *MATCH.get(&opening).expect("Match should exits") == c {
-                        stack.pop();
-                    } else {
-                        // Mis-matching characters
-                        return None;
-                    }
-                } else {
-                    // Close character with no open character
-                    return None;
+                [open, close, error_points, completion_points] => {
+                    let points = |p: &str| {
+                        p.parse()
+                            .map_err(|e| error(format!("bad points `{}`: {}", p, e)))
+                    };
+                    entries.push((
+                        open,
+                        close,
+                        points(error_points)?,
+                        points(completion_points)?,
+                    ));
+                }
+                _ => {
+                    return Err(error(format!(
+                        "expected `open close error_points completion_points`, found {} fields",
+                        v.len()
+                    )))
                 }
             }
+        }
+        Ok(BracketGrammar::new(&entries))
+    }
+}
 
-            let score = stack
-                .into_iter()
-                .map(|c| *MATCH.get(&c).expect("Matching character DNE"))
-                .rfold(0, |score, c| {
-                    score * 5
-                        + point_table
-                            .get(&c)
-                            .expect("Point table has no value for the character")
+/// Outcome of checking a single line against a grammar
+#[derive(Debug, Eq, PartialEq)]
+enum LineStatus {
+    Ok,
+    /// An unexpected closing delimiter was found at character `column`. `expected` is the
+    /// delimiter that would have closed the innermost open pair, if there was one.
+    Corrupted {
+        column: usize,
+        expected: Option<String>,
+        found: String,
+    },
+    /// The line ended with pairs still open; `missing` are the delimiters that would close them
+    Incomplete {
+        missing: String,
+    },
+}
+
+/// Pairs left open at the end of a line, innermost last, or the corruption that stopped it
+fn open_pairs(line: &str, grammar: &BracketGrammar) -> Result<Vec<usize>, LineStatus> {
+    let mut stack = vec![];
+    for (offset, token) in grammar.tokens(line) {
+        match token {
+            // We have matching characters.
+            Token::Close(pair) if stack.last() == Some(&pair) => {
+                stack.pop();
+            }
+            Token::Open(pair) => stack.push(pair),
+            // Mis-matching characters, or a close character with no open character
+            Token::Close(pair) => {
+                return Err(LineStatus::Corrupted {
+                    column: line[..offset].chars().count(),
+                    expected: stack.last().map(|&p| grammar.closing(p).to_string()),
+                    found: grammar.closing(pair).to_string(),
                 });
-            Some(score)
+            }
+        }
+    }
+    Ok(stack)
+}
+
+fn check_line(line: &str, grammar: &BracketGrammar) -> LineStatus {
+    let stack = match open_pairs(line, grammar) {
+        Ok(stack) => stack,
+        Err(corrupted) => return corrupted,
+    };
+    if stack.is_empty() {
+        return LineStatus::Ok;
+    }
+    LineStatus::Incomplete {
+        missing: stack
+            .into_iter()
+            .rev()
+            .map(|p| grammar.closing(p))
+            .collect(),
+    }
+}
+
+fn completion_score(s: &str, grammar: &BracketGrammar) -> u64 {
+    let mut v: Vec<u64> = s
+        .lines()
+        .filter_map(|line| open_pairs(line, grammar).ok())
+        .map(|stack| {
+            stack.into_iter().rev().fold(0, |score, pair| {
+                score * 5
+                    + grammar
+                        .completion_points
+                        .get(grammar.closing(pair))
+                        .expect("Point table has no value for the character")
+            })
         })
         .collect();
 
@@ -94,31 +365,625 @@ fn completion_score(s: &str, point_table: &HashMap<char, u64>) -> u64 {
     v[v.len() / 2]
 }
 
-fn syntax_error_score(s: &str, point_table: &HashMap<char, u64>) -> u64 {
-    s.lines().fold(0, |score, line| {
-        let mut stack = vec![];
-        for c in line.chars() {
-            if OPENING_CHARS.contains(&c) {
-                stack.push(c);
+fn syntax_error_score(s: &str, grammar: &BracketGrammar) -> u64 {
+    s.lines()
+        .fold(0, |score, line| match check_line(line, grammar) {
+            LineStatus::Corrupted { found, .. } => {
+                score
+                    + grammar
+                        .error_points
+                        .get(&found)
+                        .expect("Character not in point table")
+            }
+            _ => score,
+        })
+}
+
+/// Describe a problem with a line the way a compiler would, with a caret under the offending
+/// column. Returns `None` for lines without problems.
+fn diagnostic(path: &str, line_no: usize, line: &str, status: &LineStatus) -> Option<String> {
+    let (message, column, label) = match status {
+        LineStatus::Ok => return None,
+        LineStatus::Corrupted {
+            column,
+            expected: Some(expected),
+            found,
+        } => (
+            format!("mismatched closing delimiter `{}`", found),
+            *column,
+            format!("expected `{}`", expected),
+        ),
+        LineStatus::Corrupted {
+            column,
+            expected: None,
+            found,
+        } => (
+            format!("unexpected closing delimiter `{}`", found),
+            *column,
+            "nothing to close".to_string(),
+        ),
+        LineStatus::Incomplete { missing } => (
+            "unclosed delimiters".to_string(),
+            line.chars().count(),
+            format!("missing `{}`", missing),
+        ),
+    };
+
+    let gutter = " ".repeat(line_no.to_string().len());
+    Some(format!(
+        "error: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}^ {}\n",
+        message,
+        gutter,
+        path,
+        line_no,
+        column + 1,
+        gutter,
+        line_no,
+        line,
+        gutter,
+        " ".repeat(column),
+        label
+    ))
+}
+
+/// Structure of a single line
+#[derive(Debug, Default, Eq, PartialEq)]
+struct LineStats {
+    /// Deepest nesting reached before the line ends or is corrupted
+    max_depth: usize,
+    /// Number of times each delimiter appears
+    counts: HashMap<String, usize>,
+    /// Character column and length of the longest balanced substring
+    longest_balanced: (usize, usize),
+}
+
+fn line_stats(line: &str, grammar: &BracketGrammar) -> LineStats {
+    let mut stats = LineStats::default();
+    let tokens: Vec<(usize, Token)> = grammar.tokens(line).collect();
+    let column = |offset: usize| line[..offset].chars().count();
+
+    // depth stops counting once the line is corrupted
+    let mut depth_stack = vec![];
+    let mut corrupted = false;
+    // unmatched openers since the last closing delimiter nothing can balance
+    let mut stack: Vec<(usize, usize)> = vec![];
+    let mut barrier = 0;
+    for (k, &(offset, token)) in tokens.iter().enumerate() {
+        let delim = grammar.delimiter(token);
+        *stats.counts.entry(delim.to_string()).or_default() += 1;
+
+        match token {
+            Token::Close(pair) if depth_stack.last() == Some(&pair) => {
+                depth_stack.pop();
+            }
+            Token::Open(pair) if !corrupted => {
+                depth_stack.push(pair);
+                stats.max_depth = stats.max_depth.max(depth_stack.len());
+            }
+            Token::Open(_) => {}
+            Token::Close(_) => corrupted = true,
+        }
+
+        match token {
+            Token::Open(pair) => stack.push((k, pair)),
+            Token::Close(pair) if stack.last().map(|t| t.1) == Some(pair) => {
+                stack.pop();
+                let begin = stack.last().map_or(barrier, |t| t.0 + 1);
+                let start = column(tokens[begin].0);
+                let len = column(offset + delim.len()) - start;
+                if len > stats.longest_balanced.1 {
+                    stats.longest_balanced = (start, len);
+                }
+            }
+            Token::Close(_) => {
+                stack.clear();
+                barrier = k + 1;
+            }
+        }
+    }
+    stats
+}
+
+/// Structure of every line of an input
+#[derive(Debug)]
+struct InputStats {
+    lines: Vec<LineStats>,
+    /// Number of corrupted lines failing at each column
+    failure_columns: BTreeMap<usize, usize>,
+}
+
+fn input_stats(s: &str, grammar: &BracketGrammar) -> InputStats {
+    let mut failure_columns = BTreeMap::new();
+    let lines = s
+        .lines()
+        .map(|line| {
+            if let LineStatus::Corrupted { column, .. } = check_line(line, grammar) {
+                *failure_columns.entry(column).or_default() += 1;
+            }
+            line_stats(line, grammar)
+        })
+        .collect();
+    InputStats {
+        lines,
+        failure_columns,
+    }
+}
+
+impl std::fmt::Display for InputStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut delimiters: Vec<&String> =
+            self.lines.iter().flat_map(|l| l.counts.keys()).collect();
+        delimiters.sort_unstable();
+        delimiters.dedup();
+
+        write!(f, "{:>6} {:>6} {:>16}", "line", "depth", "longest (col)")?;
+        for d in &delimiters {
+            write!(f, " {:>6}", d)?;
+        }
+        writeln!(f)?;
+        for (i, stats) in self.lines.iter().enumerate() {
+            let (column, len) = stats.longest_balanced;
+            write!(
+                f,
+                "{:>6} {:>6} {:>16}",
+                i + 1,
+                stats.max_depth,
+                format!("{} ({})", len, column + 1)
+            )?;
+            for d in &delimiters {
+                write!(f, " {:>6}", stats.counts.get(*d).unwrap_or(&0))?;
+            }
+            writeln!(f)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{:>6} {:>8}", "column", "failures")?;
+        for (column, n) in &self.failure_columns {
+            writeln!(f, "{:>6} {:>8}", column + 1, n)?;
+        }
+        Ok(())
+    }
+}
+
+/// Location in a stream. Lines and columns count from zero, columns in characters.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+struct Position {
+    line: usize,
+    column: usize,
+    offset: usize,
+}
+
+/// Outcome of checking a line, or a whole document, read from a stream
+#[derive(Debug, Eq, PartialEq)]
+enum StreamStatus {
+    Ok,
+    /// An unexpected closing delimiter starting `at` the given position
+    Corrupted {
+        at: Position,
+        expected: Option<String>,
+        found: String,
+    },
+    /// Input ended `at` the given position with pairs still open
+    Incomplete {
+        at: Position,
+        missing: String,
+    },
+}
+
+/// Checks delimiters from a stream of bytes, yielding a status for each line, or for the whole
+/// input when `document` is set. Only the open pairs and enough bytes to recognise the longest
+/// delimiter are held in memory. A document stops at its first corruption.
+struct StreamChecker<'a, I: Iterator<Item = u8>> {
+    grammar: &'a BracketGrammar,
+    bytes: I,
+    document: bool,
+    window: VecDeque<u8>,
+    longest: usize,
+    /// Every delimiter, in the order `BracketGrammar::delimiters` tries them
+    delimiters: Vec<(Vec<u8>, Token)>,
+    pos: Position,
+    stack: Vec<usize>,
+    corrupted: bool,
+    line_started: bool,
+    done: bool,
+}
+
+impl<'a, I: Iterator<Item = u8>> StreamChecker<'a, I> {
+    fn new(bytes: I, grammar: &'a BracketGrammar, document: bool) -> Self {
+        let delimiters: Vec<(Vec<u8>, Token)> = grammar
+            .delimiters()
+            .into_iter()
+            .map(|(delim, token)| (delim.as_bytes().to_vec(), token))
+            .collect();
+        let longest = delimiters.first().map_or(1, |(delim, _)| delim.len());
+        Self {
+            grammar,
+            bytes,
+            document,
+            window: VecDeque::with_capacity(longest),
+            longest,
+            delimiters,
+            pos: Position::default(),
+            stack: vec![],
+            corrupted: false,
+            line_started: false,
+            done: false,
+        }
+    }
+
+    /// Length and token of the longest delimiter at the front of the window
+    fn delimiter(&mut self) -> Option<(usize, Token)> {
+        let window = self.window.make_contiguous();
+        self.delimiters
+            .iter()
+            .find(|(delim, _)| window.starts_with(delim))
+            .map(|(delim, token)| (delim.len(), *token))
+    }
+
+    fn advance(&mut self, len: usize) {
+        for b in self.window.drain(..len) {
+            self.pos.offset += 1;
+            if b == b'\n' {
+                self.pos.line += 1;
+                self.pos.column = 0;
+            } else if b & 0xC0 != 0x80 {
+                // count the first byte of each character
+                self.pos.column += 1;
+            }
+        }
+    }
+
+    /// Status of the pairs left open at the current position
+    fn finish(&mut self) -> StreamStatus {
+        if self.stack.is_empty() {
+            return StreamStatus::Ok;
+        }
+        let missing = self
+            .stack
+            .drain(..)
+            .rev()
+            .map(|p| self.grammar.closing(p))
+            .collect();
+        StreamStatus::Incomplete {
+            at: self.pos,
+            missing,
+        }
+    }
+}
+
+impl<'a, I: Iterator<Item = u8>> Iterator for StreamChecker<'a, I> {
+    type Item = StreamStatus;
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            while self.window.len() < self.longest {
+                match self.bytes.next() {
+                    Some(b) => self.window.push_back(b),
+                    None => break,
+                }
+            }
+
+            if self.window.is_empty() {
+                self.done = true;
+                if self.document || (self.line_started && !self.corrupted) {
+                    return Some(self.finish());
+                }
+                return None;
+            }
+
+            let at = self.pos;
+            let found = self.delimiter();
+            if found.is_none() && self.window[0] == b'\n' && !self.document {
+                // end of line
+                let status = self.finish();
+                self.advance(1);
+                let corrupted = std::mem::take(&mut self.corrupted);
+                self.line_started = false;
+                if !corrupted {
+                    return Some(status);
+                }
                 continue;
             }
 
-            // Otherwise closing character must match the last character
-            if let Some(&opening) = stack.last() {
+            self.line_started = true;
+            let (len, token) = match found {
+                Some(found) => found,
+                None => {
+                    self.advance(1);
+                    continue;
+                }
+            };
+            self.advance(len);
+            if self.corrupted {
+                continue;
+            }
+            match token {
                 // We have matching characters.
-                if *MATCH.get(&opening).expect("Match should exits") == c {
-                    stack.pop();
-                } else {
-                    // Mis-matching characters
-                    return score + point_table.get(&c).expect("Character not in point table");
+                Token::Close(pair) if self.stack.last() == Some(&pair) => {
+                    self.stack.pop();
+                }
+                Token::Open(pair) => self.stack.push(pair),
+                // Mis-matching characters, or a close character with no open character
+                Token::Close(pair) => {
+                    self.corrupted = true;
+                    self.done = self.document;
+                    return Some(StreamStatus::Corrupted {
+                        at,
+                        expected: self
+                            .stack
+                            .last()
+                            .map(|&p| self.grammar.closing(p).to_string()),
+                        found: self.grammar.closing(pair).to_string(),
+                    });
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Cost of each kind of edit made while repairing a line. Insertions and deletions are keyed by
+/// the delimiter inserted or deleted, substitutions by the delimiter written in place of the old
+/// one.
+#[derive(Debug)]
+struct RepairCosts {
+    insert: HashMap<String, u64>,
+    delete: HashMap<String, u64>,
+    substitute: HashMap<String, u64>,
+}
+
+impl RepairCosts {
+    /// Every edit of every delimiter in the grammar costs the same
+    fn uniform(grammar: &BracketGrammar, cost: u64) -> Self {
+        let table: HashMap<String, u64> = grammar
+            .pairs
+            .iter()
+            .flat_map(|(open, close)| [(open.clone(), cost), (close.clone(), cost)])
+            .collect();
+        Self {
+            insert: table.clone(),
+            delete: table.clone(),
+            substitute: table,
+        }
+    }
+
+    fn insert(&self, delimiter: &str) -> u64 {
+        *self
+            .insert
+            .get(delimiter)
+            .expect("Delimiter not in insertion table")
+    }
+
+    fn delete(&self, delimiter: &str) -> u64 {
+        *self
+            .delete
+            .get(delimiter)
+            .expect("Delimiter not in deletion table")
+    }
+
+    /// Cost of turning `from` into `to`, which is free if they are already the same
+    fn substitute(&self, from: &str, to: &str) -> u64 {
+        if from == to {
+            return 0;
+        }
+        *self
+            .substitute
+            .get(to)
+            .expect("Delimiter not in substitution table")
+    }
+}
+
+/// A single change made to a line, at a character column of the original line
+#[derive(Debug, Eq, PartialEq)]
+enum Edit {
+    Insert {
+        column: usize,
+        delimiter: String,
+    },
+    Delete {
+        column: usize,
+        delimiter: String,
+    },
+    Substitute {
+        column: usize,
+        from: String,
+        to: String,
+    },
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct Repair {
+    line: String,
+    edits: Vec<Edit>,
+    cost: u64,
+}
+
+/// How the first element of a balanced interval of tokens was produced
+#[derive(Debug, Clone, Copy)]
+enum Choice {
+    /// Drop the first token
+    Delete,
+    /// The first token opens `pair` and the token at `k` closes it
+    Match { pair: usize, k: usize },
+    /// The first token opens `pair`, closed by an inserted delimiter before the token at `k`
+    InsertClose { pair: usize, k: usize },
+    /// An inserted delimiter opens `pair` and the token at `k` closes it
+    InsertOpen { pair: usize, k: usize },
+}
+
+/// Item of a repaired line
+enum Piece {
+    /// Original token at index `i`, deleted when `None`
+    Token(usize, Option<String>),
+    /// New delimiter inserted before the token at index `i`
+    Inserted(usize, String),
+}
+
+enum Work {
+    Interval(usize, usize),
+    Emit(Piece),
+}
+
+/// Find the cheapest set of insertions, deletions and substitutions of delimiters that balances
+/// `line`. Text that isn't a delimiter is left where it is. When edits tie, closing a pair by
+/// insertion is preferred over deleting its opener.
+fn repair_line(line: &str, grammar: &BracketGrammar, costs: &RepairCosts) -> Repair {
+    let tokens: Vec<(usize, Token)> = grammar.tokens(line).collect();
+    let n = tokens.len();
+    let delim = |i: usize| grammar.delimiter(tokens[i].1);
+
+    // look up every cost once up front, as_open[i][pair] being the cost of turning token i into
+    // the opening delimiter of pair and so on
+    let pairs = grammar.pairs.len();
+    let insert_open: Vec<u64> = (0..pairs)
+        .map(|p| costs.insert(grammar.opening(p)))
+        .collect();
+    let insert_close: Vec<u64> = (0..pairs)
+        .map(|p| costs.insert(grammar.closing(p)))
+        .collect();
+    let delete: Vec<u64> = (0..n).map(|i| costs.delete(delim(i))).collect();
+    let as_open: Vec<Vec<u64>> = (0..n)
+        .map(|i| {
+            (0..pairs)
+                .map(|p| costs.substitute(delim(i), grammar.opening(p)))
+                .collect()
+        })
+        .collect();
+    let as_close: Vec<Vec<u64>> = (0..n)
+        .map(|i| {
+            (0..pairs)
+                .map(|p| costs.substitute(delim(i), grammar.closing(p)))
+                .collect()
+        })
+        .collect();
+
+    // best[i][j] is the cost and choice that balances the tokens in i..j
+    let mut best = vec![vec![(0, None); n + 1]; n + 1];
+    for len in 1..=n {
+        for i in 0..=n - len {
+            let j = i + len;
+            let mut cost = u64::MAX;
+            let mut choice = Choice::Delete;
+            let mut consider = |c: u64, ch: Choice| {
+                if c < cost {
+                    cost = c;
+                    choice = ch;
+                }
+            };
+            for pair in 0..pairs {
+                for k in i + 1..=j {
+                    let inside = as_open[i][pair] + best[i + 1][k].0;
+                    if k < j {
+                        consider(
+                            inside + as_close[k][pair] + best[k + 1][j].0,
+                            Choice::Match { pair, k },
+                        );
+                    }
+                    consider(
+                        inside + insert_close[pair] + best[k][j].0,
+                        Choice::InsertClose { pair, k },
+                    );
+                }
+                for k in i..j {
+                    consider(
+                        insert_open[pair] + best[i][k].0 + as_close[k][pair] + best[k + 1][j].0,
+                        Choice::InsertOpen { pair, k },
+                    );
                 }
-            } else {
-                // Close character with no open character
-                return score + point_table.get(&c).expect("Character not in point table");
             }
+            consider(delete[i] + best[i + 1][j].0, Choice::Delete);
+            best[i][j] = (cost, Some(choice));
         }
-        score
-    })
+    }
+
+    // walk the choices back into the pieces of the repaired line, pushing work in reverse so
+    // that pieces come out left to right
+    let mut pieces = vec![];
+    let mut work = vec![Work::Interval(0, n)];
+    while let Some(w) = work.pop() {
+        let (i, j) = match w {
+            Work::Emit(piece) => {
+                pieces.push(piece);
+                continue;
+            }
+            Work::Interval(i, j) => (i, j),
+        };
+        let open = |pair| grammar.opening(pair).to_string();
+        let close = |pair| grammar.closing(pair).to_string();
+        match best[i][j].1 {
+            None => {}
+            Some(Choice::Delete) => {
+                work.push(Work::Interval(i + 1, j));
+                work.push(Work::Emit(Piece::Token(i, None)));
+            }
+            Some(Choice::Match { pair, k }) => {
+                work.push(Work::Interval(k + 1, j));
+                work.push(Work::Emit(Piece::Token(k, Some(close(pair)))));
+                work.push(Work::Interval(i + 1, k));
+                work.push(Work::Emit(Piece::Token(i, Some(open(pair)))));
+            }
+            Some(Choice::InsertClose { pair, k }) => {
+                work.push(Work::Interval(k, j));
+                work.push(Work::Emit(Piece::Inserted(k, close(pair))));
+                work.push(Work::Interval(i + 1, k));
+                work.push(Work::Emit(Piece::Token(i, Some(open(pair)))));
+            }
+            Some(Choice::InsertOpen { pair, k }) => {
+                work.push(Work::Interval(k + 1, j));
+                work.push(Work::Emit(Piece::Token(k, Some(close(pair)))));
+                work.push(Work::Interval(i, k));
+                work.push(Work::Emit(Piece::Inserted(i, open(pair))));
+            }
+        }
+    }
+
+    // stitch the pieces back together with the text between delimiters
+    let column = |offset: usize| line[..offset].chars().count();
+    let mut repaired = String::new();
+    let mut edits = vec![];
+    let mut cursor = 0;
+    for piece in pieces {
+        match piece {
+            Piece::Token(i, to) => {
+                let start = tokens[i].0;
+                let from = delim(i);
+                repaired.push_str(&line[cursor..start]);
+                cursor = start + from.len();
+                match to {
+                    None => edits.push(Edit::Delete {
+                        column: column(start),
+                        delimiter: from.to_string(),
+                    }),
+                    Some(to) => {
+                        repaired.push_str(&to);
+                        if to != from {
+                            edits.push(Edit::Substitute {
+                                column: column(start),
+                                from: from.to_string(),
+                                to,
+                            });
+                        }
+                    }
+                }
+            }
+            Piece::Inserted(i, delimiter) => {
+                let at = tokens.get(i).map_or(line.len(), |t| t.0);
+                repaired.push_str(&line[cursor..at]);
+                cursor = at;
+                repaired.push_str(&delimiter);
+                edits.push(Edit::Insert {
+                    column: column(at),
+                    delimiter,
+                });
+            }
+        }
+    }
+    repaired.push_str(&line[cursor..]);
+
+    Repair {
+        line: repaired,
+        edits,
+        cost: best[0][n].0,
+    }
 }
 
 #[cfg(test)]
@@ -128,32 +993,310 @@ mod tests {
     #[test]
     fn part_two_works() {
         let s = include_str!("day10.test.txt");
-        let score = completion_score(s, &COMPLETION_POINT_TABLE);
+        let score = completion_score(s, &DEFAULT_GRAMMAR);
         assert_eq!(score, 288957)
     }
 
     #[test]
     fn part_two_simple_works() {
         let s = "(";
-        let score = completion_score(s, &COMPLETION_POINT_TABLE);
+        let score = completion_score(s, &DEFAULT_GRAMMAR);
         assert_eq!(score, 1);
 
         let s = "({";
-        let score = completion_score(s, &COMPLETION_POINT_TABLE);
+        let score = completion_score(s, &DEFAULT_GRAMMAR);
         assert_eq!(score, 16);
     }
 
+    #[test]
+    fn multi_character_grammar_works() {
+        let grammar = BracketGrammar::new(&[
+            ("begin", "end", 10, 1),
+            ("/*", "*/", 20, 2),
+            ("(", ")", 30, 3),
+        ]);
+        let s = "begin x := (1) /* ok */ end\nbegin /* end\nbegin ( /* */";
+        assert_eq!(syntax_error_score(s, &grammar), 10);
+        // remaining closers are ")" then "end": (0 * 5 + 3) * 5 + 1
+        assert_eq!(completion_score("begin ( /* */", &grammar), 16);
+    }
+
+    #[test]
+    fn grammar_from_str_works() {
+        let grammar: BracketGrammar = "( ) 3 1\n[ ] 57 2\n{ } 1197 3\n< > 25137 4\n"
+            .parse()
+            .unwrap();
+        let s = include_str!("day10.test.txt");
+        assert_eq!(syntax_error_score(s, &grammar), 26397);
+        assert_eq!(completion_score(s, &grammar), 288957);
+    }
+
+    #[test]
+    fn bad_grammars_are_rejected() {
+        let e = "( ) 3 1\n[ ] 57\n".parse::<BracketGrammar>().unwrap_err();
+        assert_eq!(e.line, 2);
+        let e = "| | 1 1\n".parse::<BracketGrammar>().unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "line 1: `|` can't both open and close a pair"
+        );
+        let e = "( ) 3 x\n".parse::<BracketGrammar>().unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "line 1: bad points `x`: invalid digit found in string"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Delimiters can't be empty")]
+    fn empty_delimiters_are_rejected() {
+        BracketGrammar::new(&[("", ")", 1, 1)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "`|` can't both open and close a pair")]
+    fn identical_delimiters_are_rejected() {
+        BracketGrammar::new(&[("(", ")", 1, 1), ("|", "|", 1, 1)]);
+    }
+
+    #[test]
+    fn check_line_works() {
+        let status = check_line("{([(<{}[<>[]}>{[]{[(<()>", &DEFAULT_GRAMMAR);
+        assert_eq!(
+            status,
+            LineStatus::Corrupted {
+                column: 12,
+                expected: Some("]".to_string()),
+                found: "}".to_string()
+            }
+        );
+
+        let status = check_line("[({(<(())[]>[[{[]{<()<>>", &DEFAULT_GRAMMAR);
+        assert_eq!(
+            status,
+            LineStatus::Incomplete {
+                missing: "}}]])})]".to_string()
+            }
+        );
+
+        assert_eq!(check_line("<>()", &DEFAULT_GRAMMAR), LineStatus::Ok);
+    }
+
+    #[test]
+    fn overlapping_closers_are_scored_separately() {
+        let grammar = BracketGrammar::new(&[("{", "}", 1, 1), ("{{", "}}", 10, 7)]);
+        // two single braces still open, not one double brace
+        assert_eq!(completion_score("{ {", &grammar), 6);
+        assert_eq!(completion_score("{{", &grammar), 7);
+    }
+
+    #[test]
+    fn diagnostic_works() {
+        let line = "{([(<{}[<>[]}>{[]{[(<()>";
+        let status = check_line(line, &DEFAULT_GRAMMAR);
+        let d = diagnostic("in.txt", 3, line, &status).unwrap();
+        assert_eq!(
+            d,
+            r#"error: mismatched closing delimiter `}`
+ --> in.txt:3:13
+  |
+3 | {([(<{}[<>[]}>{[]{[(<()>
+  |             ^ expected `]`
+"#
+        );
+        assert_eq!(diagnostic("in.txt", 1, "()", &LineStatus::Ok), None);
+    }
+
+    #[test]
+    fn repair_line_works() {
+        let costs = RepairCosts::uniform(&DEFAULT_GRAMMAR, 1);
+
+        // turning "(" into "]" is a single edit
+        let repair = repair_line("[(x", &DEFAULT_GRAMMAR, &costs);
+        assert_eq!(repair.line, "[]x");
+        assert_eq!(repair.cost, 1);
+
+        // completing is preferred to deleting at the same cost
+        let repair = repair_line("<", &DEFAULT_GRAMMAR, &costs);
+        assert_eq!(repair.line, "<>");
+        assert_eq!(
+            repair.edits,
+            vec![Edit::Insert {
+                column: 1,
+                delimiter: ">".to_string()
+            }]
+        );
+
+        let repair = repair_line("{([(<{}[<>[]}>{[]{[(<()>", &DEFAULT_GRAMMAR, &costs);
+        assert_eq!(check_line(&repair.line, &DEFAULT_GRAMMAR), LineStatus::Ok);
+
+        assert_eq!(
+            repair_line("<>()", &DEFAULT_GRAMMAR, &costs),
+            Repair {
+                line: "<>()".to_string(),
+                edits: vec![],
+                cost: 0
+            }
+        );
+    }
+
+    #[test]
+    fn repair_line_uses_costs() {
+        let mut costs = RepairCosts::uniform(&DEFAULT_GRAMMAR, 1);
+        costs.substitute.insert("]".to_string(), 5);
+        costs.substitute.insert(")".to_string(), 1);
+
+        // swapping the closer is cheaper than inserting a delimiter
+        let repair = repair_line("(]", &DEFAULT_GRAMMAR, &costs);
+        assert_eq!(repair.line, "()");
+        assert_eq!(
+            repair.edits,
+            vec![Edit::Substitute {
+                column: 1,
+                from: "]".to_string(),
+                to: ")".to_string()
+            }]
+        );
+
+        // unless substitution is the most expensive edit
+        costs.substitute.insert(")".to_string(), 5);
+        costs.substitute.insert("[".to_string(), 5);
+        let repair = repair_line("(]", &DEFAULT_GRAMMAR, &costs);
+        assert_eq!(repair.cost, 2);
+        assert_eq!(repair.line, "()[]");
+    }
+
+    #[test]
+    fn stream_checker_matches_check_line() {
+        let s = include_str!("day10.test.txt");
+        assert_stream_matches(s, &DEFAULT_GRAMMAR);
+
+        // `<` and `>` each belong to two pairs, and `((` overlaps `(`
+        let grammar = BracketGrammar::new(&[
+            ("begin", "end", 1, 1),
+            ("(", ")", 1, 1),
+            ("<", ">", 1, 1),
+            (">", "<", 1, 1),
+            ("((", "))", 1, 1),
+        ]);
+        let s = "begin ( end\n<><\nbegin(x)end <\n((>\n>\n(((\n(()))\n<begin>end\n";
+        assert_stream_matches(s, &grammar);
+    }
+
+    fn assert_stream_matches(s: &str, grammar: &BracketGrammar) {
+        let statuses: Vec<_> = StreamChecker::new(s.bytes(), grammar, false).collect();
+        assert_eq!(statuses.len(), s.lines().count());
+        for (status, line) in statuses.into_iter().zip(s.lines()) {
+            match (status, check_line(line, grammar)) {
+                (StreamStatus::Ok, LineStatus::Ok) => {}
+                (
+                    StreamStatus::Corrupted {
+                        at,
+                        expected,
+                        found,
+                    },
+                    LineStatus::Corrupted {
+                        column,
+                        expected: e,
+                        found: f,
+                    },
+                ) => {
+                    assert_eq!((at.column, expected, found), (column, e, f));
+                }
+                (
+                    StreamStatus::Incomplete { missing, .. },
+                    LineStatus::Incomplete { missing: m },
+                ) => assert_eq!(missing, m),
+                (a, b) => panic!("{:?} != {:?}", a, b),
+            }
+        }
+    }
+
+    #[test]
+    fn stream_checker_document_works() {
+        let grammar = BracketGrammar::new(&[("begin", "end", 1, 1), ("(", ")", 1, 1)]);
+        let s = "begin\n  (x)\nend\n";
+        let statuses: Vec<_> = StreamChecker::new(s.bytes(), &grammar, true).collect();
+        assert_eq!(statuses, vec![StreamStatus::Ok]);
+
+        let s = "begin\n  (x\nend\n(";
+        let statuses: Vec<_> = StreamChecker::new(s.bytes(), &grammar, true).collect();
+        assert_eq!(
+            statuses,
+            vec![StreamStatus::Corrupted {
+                at: Position {
+                    line: 2,
+                    column: 0,
+                    offset: 11
+                },
+                expected: Some(")".to_string()),
+                found: "end".to_string()
+            }]
+        );
+
+        let statuses: Vec<_> = StreamChecker::new("(\n".bytes(), &grammar, true).collect();
+        assert_eq!(
+            statuses,
+            vec![StreamStatus::Incomplete {
+                at: Position {
+                    line: 1,
+                    column: 0,
+                    offset: 2
+                },
+                missing: ")".to_string()
+            }]
+        );
+
+        // per line, an unfinished line ends at its newline
+        let statuses: Vec<_> = StreamChecker::new("(\n".bytes(), &grammar, false).collect();
+        assert_eq!(
+            statuses,
+            vec![StreamStatus::Incomplete {
+                at: Position {
+                    line: 0,
+                    column: 1,
+                    offset: 1
+                },
+                missing: ")".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn line_stats_works() {
+        let stats = line_stats("(()x[<>]]{}{}()", &DEFAULT_GRAMMAR);
+        assert_eq!(stats.max_depth, 3);
+        assert_eq!(stats.counts["("], 3);
+        assert_eq!(stats.counts["]"], 2);
+        assert_eq!(stats.counts.get("{").copied(), Some(2));
+        // "()x[<>]", text between delimiters included
+        assert_eq!(stats.longest_balanced, (1, 7));
+
+        let stats = line_stats("[({})]", &DEFAULT_GRAMMAR);
+        assert_eq!(stats.max_depth, 3);
+        assert_eq!(stats.longest_balanced, (0, 6));
+    }
+
+    #[test]
+    fn input_stats_works() {
+        let s = include_str!("day10.test.txt");
+        let stats = input_stats(s, &DEFAULT_GRAMMAR);
+        assert_eq!(stats.lines.len(), 10);
+        assert_eq!(stats.failure_columns.values().sum::<usize>(), 5);
+        assert_eq!(stats.failure_columns[&12], 1);
+    }
+
     #[test]
     fn part_one_works() {
         let s = include_str!("day10.test.txt");
-        let score = syntax_error_score(s, &ERR_POINT_TABLE);
+        let score = syntax_error_score(s, &DEFAULT_GRAMMAR);
         assert_eq!(score, 26397)
     }
 
     #[test]
     fn simple_works() {
         let s = ")";
-        let score = syntax_error_score(s, &ERR_POINT_TABLE);
+        let score = syntax_error_score(s, &DEFAULT_GRAMMAR);
         assert_eq!(score, 3)
     }
 }
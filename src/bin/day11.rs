@@ -1,142 +1,546 @@
 #![feature(io_read_to_string)]
+use advent_of_code_2021::{report, Format};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
 use std::fs::File;
-use std::io::read_to_string;
+use std::hash::{Hash, Hasher};
+use std::io::{read_to_string, Write};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::time::Duration;
+use std::time::Instant;
 
 fn main() {
     let mut f = File::open("day11.1.txt").unwrap();
     let s = read_to_string(&mut f).unwrap();
+    let (format, args) = Format::from_args();
+    let start = Instant::now();
     let mut m: EnergyMap = s.parse().unwrap();
     let flashes = m.flashes_after(100);
-    println!("Part one: {}", flashes);
-    assert_eq!(flashes, 1679);
+    report(
+        format,
+        (11, 1),
+        flashes,
+        Some(1679),
+        start.elapsed(),
+        || println!("Part one: {}", flashes),
+    );
 
+    let start = Instant::now();
     let mut m: EnergyMap = s.parse().unwrap();
-    let steps = m.steps_to_simul();
-    println!("Part two: {}", steps);
-    assert_eq!(steps, 519);
+    let steps = m.steps_to_simul(1000).unwrap();
+    report(format, (11, 2), steps, Some(519), start.elapsed(), || {
+        println!("Part two: {}", steps)
+    });
+
+    let mut rules = Rules::default();
+    let mut animate = false;
+    let mut trace = false;
+    let mut flat = false;
+    // the variant run is only worth printing when the rules or engine were changed
+    let mut variant = false;
+    let mut frames_dir = None;
+    let mut fps = 10;
+    let (mut from, mut to) = (0, 100);
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| panic!("{} requires a value", arg))
+        };
+        variant |= !matches!(
+            arg.as_ref(),
+            "--animate" | "--trace" | "--fps" | "--from" | "--to" | "--frames"
+        );
+        match arg.as_ref() {
+            "--threshold" => rules.threshold = value().parse().unwrap(),
+            "--reset" => rules.reset = value().parse().unwrap(),
+            "--increment" => rules.step_increment = value().parse().unwrap(),
+            "--flash-increment" => rules.flash_increment = value().parse().unwrap(),
+            "--von-neumann" => rules.neighbourhood = Neighbourhood::VonNeumann,
+            "--wrap" => rules.wrap = true,
+            "--animate" => animate = true,
+            "--trace" => trace = true,
+            "--flat" => flat = true,
+            "--fps" => {
+                fps = value().parse().unwrap();
+                assert!(fps > 0, "--fps must be at least 1");
+            }
+            "--from" => from = value().parse().unwrap(),
+            "--to" => to = value().parse().unwrap(),
+            "--frames" => frames_dir = Some(value()),
+            _ => panic!("Unknown argument: {}", arg),
+        }
+    }
+
+    if trace {
+        let mut m: EnergyMap = s.parse().unwrap();
+        m.rules = rules;
+        for (step, events) in (1..=to).zip(m.events()).filter(|(step, _)| *step >= from) {
+            let events: Vec<String> = events
+                .iter()
+                .map(|f| format!("{},{}@{}", f.row, f.col, f.depth))
+                .collect();
+            println!("step {}: {}", step, events.join(" "));
+        }
+        return;
+    }
+
+    if animate || frames_dir.is_some() {
+        let mut m: EnergyMap = s.parse().unwrap();
+        m.rules = rules;
+        let delay = Duration::from_secs_f64(1.0 / fps as f64);
+        m.animate(from..to + 1, |step, frame| match &frames_dir {
+            // one file per frame, without any cursor movement
+            Some(dir) => std::fs::write(format!("{}/step-{:04}.txt", dir, step), frame),
+            None => {
+                let mut out = std::io::stdout();
+                write!(out, "\x1b[H\x1b[2J{}", frame)?;
+                out.flush()?;
+                std::thread::sleep(delay);
+                Ok(())
+            }
+        })
+        .unwrap();
+        return;
+    }
+
+    if !variant {
+        return;
+    }
+    let mut m: EnergyMap = s.parse().unwrap();
+    m.rules = rules.clone();
+    let flashes = if flat {
+        FlatMap::from(&m).flashes_after(100)
+    } else {
+        m.flashes_after(100)
+    };
+    println!("Variant flashes after 100 steps: {}", flashes);
+    let mut m: EnergyMap = s.parse().unwrap();
+    m.rules = rules;
+    println!(
+        "Variant steps to synchronise: {:?}",
+        m.steps_to_simul(10_000)
+    );
+}
+
+/// Cells that count as neighbours of an octopus
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+enum Neighbourhood {
+    /// The eight surrounding cells, diagonals included
+    Moore,
+    /// The four cells above, below, left and right
+    VonNeumann,
+}
+
+/// Rules the octopuses follow. The default is the puzzle's rules.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+struct Rules {
+    /// Octopuses flash once their energy is above this
+    threshold: u8,
+    /// Energy an octopus is left with after flashing
+    reset: u8,
+    /// Energy every octopus gains each step
+    step_increment: u8,
+    /// Energy each neighbour of a flashing octopus gains
+    flash_increment: u8,
+    neighbourhood: Neighbourhood,
+    /// Whether neighbourhoods wrap around the edges of the map
+    wrap: bool,
+}
+
+impl Rules {
+    /// Neighbours of a cell in a map of the given size
+    fn adjacent(&self, height: usize, width: usize, row: usize, col: usize) -> Vec<(usize, usize)> {
+        let mut v = vec![];
+        self.for_each_adjacent(height, width, row, col, |r, c| v.push((r, c)));
+        v
+    }
+
+    /// Like `adjacent`, without allocating
+    fn for_each_adjacent(
+        &self,
+        height: usize,
+        width: usize,
+        row: usize,
+        col: usize,
+        mut f: impl FnMut(usize, usize),
+    ) {
+        // upper left, upper right, lower left, lower right, upper, lower, left, right
+        const MOORE: [(isize, isize); 8] = [
+            (-1, -1),
+            (-1, 1),
+            (1, -1),
+            (1, 1),
+            (-1, 0),
+            (1, 0),
+            (0, -1),
+            (0, 1),
+        ];
+        let offsets = match self.neighbourhood {
+            Neighbourhood::Moore => &MOORE[..],
+            Neighbourhood::VonNeumann => &MOORE[4..],
+        };
+
+        let (height, width) = (height as isize, width as isize);
+        let mut seen = [(0, 0); 8];
+        let mut n = 0;
+        for (dr, dc) in offsets {
+            let (mut r, mut c) = (row as isize + dr, col as isize + dc);
+            if self.wrap {
+                r = r.rem_euclid(height);
+                c = c.rem_euclid(width);
+            } else if r < 0 || r >= height || c < 0 || c >= width {
+                continue;
+            }
+            let p = (r as usize, c as usize);
+            // a wrapped map narrower than three cells can reach the same cell more than once
+            if p != (row, col) && !seen[..n].contains(&p) {
+                seen[n] = p;
+                n += 1;
+                f(p.0, p.1);
+            }
+        }
+    }
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            threshold: 9,
+            reset: 0,
+            step_increment: 1,
+            flash_increment: 1,
+            neighbourhood: Neighbourhood::Moore,
+            wrap: false,
+        }
+    }
 }
 
+/// Reasons a map never synchronises
 #[derive(Debug, Eq, PartialEq)]
+enum SyncError {
+    /// The map returned after `period` steps to the state it was in at step `start`, so it
+    /// repeats forever without every octopus flashing at once
+    Cycle { start: u64, period: u64 },
+    /// No synchronisation or cycle was found within this many steps
+    BudgetExceeded(u64),
+}
+
+/// An octopus flashing during a step
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Flash {
+    row: usize,
+    col: usize,
+    /// Zero when the step's own increment set the octopus off, otherwise one more than the depth
+    /// of the flash that pushed it over the threshold
+    depth: usize,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 struct EnergyMap {
     map: Vec<Vec<u8>>,
+    rules: Rules,
 }
 
 impl EnergyMap {
     fn flashes_after(&mut self, steps: usize) -> u64 {
-        (0..steps).fold(0, |acc, _| acc + self.step())
+        self.events().take(steps).map(|e| e.len() as u64).sum()
     }
 
-    fn steps_to_simul(&mut self) -> u64 {
+    /// Number of steps until every octopus flashes at once, leaving them all at the reset energy.
+    /// Takes at most `budget` steps.
+    fn steps_to_simul(&mut self, budget: u64) -> Result<u64, SyncError> {
+        // Only hashes of earlier states are kept. When one matches, the earlier state is
+        // rebuilt from the start to rule out a collision.
+        let initial = self.clone();
+        let mut seen: HashMap<u64, Vec<u64>> = HashMap::new();
         let mut steps = 0;
-        while !self.map.iter().flatten().all(|v| *v == 0) {
+        while !self.map.iter().flatten().all(|v| *v == self.rules.reset) {
+            let mut hasher = DefaultHasher::new();
+            self.hash(&mut hasher);
+            let candidates = seen.entry(hasher.finish()).or_default();
+            for &start in candidates.iter() {
+                let mut earlier = initial.clone();
+                (0..start).for_each(|_| {
+                    earlier.step();
+                });
+                if earlier == *self {
+                    return Err(SyncError::Cycle {
+                        start,
+                        period: steps - start,
+                    });
+                }
+            }
+            candidates.push(steps);
+            if steps == budget {
+                return Err(SyncError::BudgetExceeded(budget));
+            }
             self.step();
             steps += 1;
         }
-        steps
+        Ok(steps)
     }
 
     fn step(&mut self) -> u64 {
-        // increment all levels by 1
-        let mut flashed = vec![];
-        let mut num_flashed = 0;
+        self.step_events().len() as u64
+    }
+
+    /// Step the map forever, yielding the flashes of each step
+    fn events(&mut self) -> impl Iterator<Item = Vec<Flash>> + '_ {
+        std::iter::repeat_with(move || self.step_events())
+    }
+
+    /// Step the map, returning its flashes in the order they happened. Octopuses pushed over the
+    /// threshold by the step's increment flash first, in reading order, then the cascade is
+    /// followed breadth first.
+    fn step_events(&mut self) -> Vec<Flash> {
+        // increment all levels
+        let mut flashed = vec![vec![false; self.map[0].len()]; self.map.len()];
+        let mut queue = VecDeque::new();
         for (row, inner) in self.map.iter_mut().enumerate() {
             for (col, value) in inner.iter_mut().enumerate() {
-                *value += 1;
-                if *value > 9 {
-                    flashed.push((row, col));
+                *value = value.saturating_add(self.rules.step_increment);
+                if *value > self.rules.threshold {
+                    flashed[row][col] = true;
+                    queue.push_back(Flash { row, col, depth: 0 });
                 }
             }
         }
 
-        // octos w/ levels greater than 9 flash and cause adjectent (and diagonal) levels to increment
-        let mut visited = flashed.clone();
-        for (row, col) in flashed.into_iter() {
-            num_flashed += 1;
-            num_flashed += self.flash(row, col, &mut visited);
+        // octos w/ levels greater than the threshold flash and cause their neighbours' levels to
+        // increment. Each octo flashes at most once per step.
+        let mut events = vec![];
+        while let Some(flash) = queue.pop_front() {
+            events.push(flash);
+            for (row, col) in self.get_adjacent(flash.row, flash.col) {
+                let value = &mut self.map[row][col];
+                *value = value.saturating_add(self.rules.flash_increment);
+                if *value > self.rules.threshold && !flashed[row][col] {
+                    flashed[row][col] = true;
+                    queue.push_back(Flash {
+                        row,
+                        col,
+                        depth: flash.depth + 1,
+                    });
+                }
+            }
         }
 
-        // any octo that flashed is set to 0
-        for inner in self.map.iter_mut() {
-            for v in inner.iter_mut() {
-                if *v > 9 {
-                    *v = 0;
+        // any octo that flashed is reset
+        for (inner, flashed) in self.map.iter_mut().zip(&flashed) {
+            for (v, flashed) in inner.iter_mut().zip(flashed) {
+                if *flashed {
+                    *v = self.rules.reset;
                 }
             }
         }
-        num_flashed
+        events
     }
 
-    fn flash(&mut self, row: usize, col: usize, flashed: &mut Vec<(usize, usize)>) -> u64 {
-        flashed.push((row, col));
-        let adjacent = self.get_adjacent(row, col);
-        let mut num_flashed = 0;
+    fn get_adjacent(&self, row: usize, col: usize) -> Vec<(usize, usize)> {
+        self.rules
+            .adjacent(self.map.len(), self.map[0].len(), row, col)
+    }
 
-        for (row, col) in adjacent {
-            let value = &mut self.map[row][col];
-            *value += 1;
-            if !flashed.contains(&(row, col)) {
-                if *value > 9 {
-                    num_flashed += 1;
-                    num_flashed += self.flash(row, col, flashed);
+    /// Draw the map after `step` steps, highlighting the octopuses that just flashed
+    fn frame(&self, step: u64, flashed: &[Vec<bool>]) -> String {
+        let mut out = format!("Step {}\n", step);
+        for (inner, flashed) in self.map.iter().zip(flashed) {
+            for (v, flashed) in inner.iter().zip(flashed) {
+                if *flashed {
+                    out.push_str(&format!("\x1b[1;93m{}\x1b[0m", v));
+                } else {
+                    out.push_str(&v.to_string());
                 }
             }
+            out.push('\n');
         }
-        num_flashed
+        out
     }
 
-    fn get_adjacent(&self, row: usize, col: usize) -> Vec<(usize, usize)> {
-        let mut v = vec![];
-        let height = self.map.len();
-        let width = self.map[0].len();
-        // push upper left
-        if row > 0 && col > 0 {
-            v.push((row - 1, col - 1))
+    /// Run the map up to the end of `steps`, handing `draw` the frame of every step in the range.
+    /// Step 0 is the map as it started.
+    fn animate<F: FnMut(u64, &str) -> std::io::Result<()>>(
+        &mut self,
+        steps: Range<u64>,
+        mut draw: F,
+    ) -> std::io::Result<()> {
+        let mut flashed = vec![vec![false; self.map[0].len()]; self.map.len()];
+        for step in 0..steps.end {
+            if step > 0 {
+                flashed = vec![vec![false; self.map[0].len()]; self.map.len()];
+                for flash in self.step_events() {
+                    flashed[flash.row][flash.col] = true;
+                }
+            }
+            if step >= steps.start {
+                draw(step, &self.frame(step, &flashed))?;
+            }
         }
+        Ok(())
+    }
+}
 
-        // push upper right
-        if row > 0 && col < width - 1 {
-            v.push((row - 1, col + 1))
-        }
+/// Energy levels stored row after row in a single buffer. Each step splits the map into bands
+/// of rows with a thread per band. A thread follows cascades through its own band and passes
+/// increments for cells in other bands on to their owners.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct FlatMap {
+    width: usize,
+    height: usize,
+    cells: Vec<u8>,
+    rules: Rules,
+}
 
-        // push lower left
-        if row < height - 1 && col > 0 {
-            v.push((row + 1, col - 1))
+impl From<&EnergyMap> for FlatMap {
+    fn from(m: &EnergyMap) -> Self {
+        Self {
+            width: m.map[0].len(),
+            height: m.map.len(),
+            cells: m.map.iter().flatten().copied().collect(),
+            rules: m.rules.clone(),
         }
+    }
+}
 
-        // push lower right
-        if row < height - 1 && col < width - 1 {
-            v.push((row + 1, col + 1))
-        }
+/// Work handed from one band to another during a step
+enum Message {
+    /// Flashes next door give these cells another flash increment
+    Increment(Vec<usize>),
+    /// Every cascade has finished
+    Done,
+}
 
-        // push upper
-        if row > 0 {
-            v.push((row - 1, col));
-        }
-        // push lower
-        if row < height - 1 {
-            v.push((row + 1, col));
-        }
+/// One band's share of a `FlatMap` step
+struct Band<'a> {
+    rules: &'a Rules,
+    height: usize,
+    width: usize,
+    /// Cells in every band but the last
+    size: usize,
+    /// Index of the band's first cell in the whole map
+    start: usize,
+    cells: &'a mut [u8],
+    flashed: Vec<bool>,
+    /// Octopuses in this band that flashed but haven't lit their neighbours yet
+    queue: Vec<usize>,
+    /// Increments waiting to be sent to each band
+    outgoing: Vec<Vec<usize>>,
+    flashes: u64,
+}
 
-        // push left
-        if col > 0 {
-            v.push((row, col - 1));
+impl Band<'_> {
+    fn bump(&mut self, j: usize, increment: u8) {
+        let i = j - self.start;
+        let v = &mut self.cells[i];
+        *v = v.saturating_add(increment);
+        if *v > self.rules.threshold && !self.flashed[i] {
+            self.flashed[i] = true;
+            self.flashes += 1;
+            self.queue.push(j);
         }
-        // push right
-        if col < width - 1 {
-            v.push((row, col + 1))
+    }
+
+    /// Follow cascades as far as this band reaches, setting aside increments for other bands
+    fn cascade(&mut self) {
+        let (rules, height, width) = (self.rules, self.height, self.width);
+        let own = self.start / self.size;
+        while let Some(i) = self.queue.pop() {
+            rules.for_each_adjacent(height, width, i / width, i % width, |r, c| {
+                let j = r * width + c;
+                if j / self.size == own {
+                    self.bump(j, rules.flash_increment);
+                } else {
+                    self.outgoing[j / self.size].push(j);
+                }
+            });
         }
-        v
     }
+}
 
-    #[allow(dead_code)]
-    fn print(&self) {
-        for inner in self.map.iter() {
-            for v in inner.iter() {
-                print!("{}", v);
-            }
-            println!();
+impl FlatMap {
+    fn flashes_after(&mut self, steps: usize) -> u64 {
+        (0..steps).fold(0, |acc, _| acc + self.step())
+    }
+
+    fn step(&mut self) -> u64 {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        self.step_in_bands(threads)
+    }
+
+    /// Step the map, working on up to `bands` bands of rows at once
+    fn step_in_bands(&mut self, bands: usize) -> u64 {
+        if self.cells.is_empty() {
+            return 0;
         }
-        println!("=================================")
+        let size = self.height.div_ceil(bands.max(1)) * self.width;
+        let count = self.cells.len().div_ceil(size);
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..count).map(|_| mpsc::channel()).unzip();
+        // bands still on their first pass, plus batches of increments not yet applied
+        let work = &AtomicUsize::new(count);
+        let (rules, height, width) = (&self.rules, self.height, self.width);
+
+        std::thread::scope(|s| {
+            let handles: Vec<_> = self
+                .cells
+                .chunks_mut(size)
+                .zip(receivers)
+                .enumerate()
+                .map(|(b, (cells, rx))| {
+                    let senders = senders.clone();
+                    s.spawn(move || {
+                        let mut band = Band {
+                            rules,
+                            height,
+                            width,
+                            size,
+                            start: b * size,
+                            flashed: vec![false; cells.len()],
+                            cells,
+                            queue: vec![],
+                            outgoing: vec![vec![]; count],
+                            flashes: 0,
+                        };
+                        for j in band.start..band.start + band.cells.len() {
+                            band.bump(j, rules.step_increment);
+                        }
+                        loop {
+                            band.cascade();
+                            for (to, batch) in band.outgoing.iter_mut().enumerate() {
+                                if !batch.is_empty() {
+                                    work.fetch_add(1, Ordering::SeqCst);
+                                    let batch = std::mem::take(batch);
+                                    senders[to].send(Message::Increment(batch)).unwrap();
+                                }
+                            }
+                            // the last piece of work to finish tells every band to stop
+                            if work.fetch_sub(1, Ordering::SeqCst) == 1 {
+                                senders
+                                    .iter()
+                                    .for_each(|tx| tx.send(Message::Done).unwrap());
+                            }
+                            match rx.recv().unwrap() {
+                                Message::Increment(batch) => batch
+                                    .into_iter()
+                                    .for_each(|j| band.bump(j, rules.flash_increment)),
+                                Message::Done => break,
+                            }
+                        }
+
+                        // any octo that flashed is reset
+                        for (v, f) in band.cells.iter_mut().zip(&band.flashed) {
+                            if *f {
+                                *v = rules.reset;
+                            }
+                        }
+                        band.flashes
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).sum()
+        })
     }
 }
 
@@ -152,7 +556,10 @@ impl std::str::FromStr for EnergyMap {
             })
             .collect();
 
-        Ok(Self { map })
+        Ok(Self {
+            map,
+            rules: Rules::default(),
+        })
     }
 }
 
@@ -163,9 +570,26 @@ mod test {
     fn first_simul_flash_works() {
         let s = include_str!("day11.test.txt");
         let mut m: EnergyMap = s.parse().unwrap();
-        let ans = m.steps_to_simul();
-        assert_eq!(ans, 195);
+        let ans = m.steps_to_simul(1000);
+        assert_eq!(ans, Ok(195));
+
+        let mut m: EnergyMap = s.parse().unwrap();
+        let ans = m.steps_to_simul(100);
+        assert_eq!(ans, Err(SyncError::BudgetExceeded(100)));
     }
+    #[test]
+    fn never_synchronising_cycle_is_found() {
+        let mut m: EnergyMap = "00002".parse().unwrap();
+        let ans = m.steps_to_simul(1000);
+        assert_eq!(
+            ans,
+            Err(SyncError::Cycle {
+                start: 0,
+                period: 9
+            })
+        );
+    }
+
     #[test]
     fn part_one_works() {
         let s = include_str!("day11.test.txt");
@@ -174,6 +598,163 @@ mod test {
         assert_eq!(flashes, 1656);
     }
 
+    #[test]
+    fn rules_can_be_changed() {
+        let mut m: EnergyMap = "000\n050\n000".parse().unwrap();
+        m.rules.step_increment = 5;
+        m.rules.flash_increment = 2;
+        m.rules.reset = 1;
+        m.rules.neighbourhood = Neighbourhood::VonNeumann;
+        assert_eq!(m.step(), 1);
+        let expected: EnergyMap = "575\n717\n575".parse().unwrap();
+        assert_eq!(m.map, expected.map);
+
+        m.rules.threshold = 7;
+        assert_eq!(m.step(), 9);
+        assert!(m.map.iter().flatten().all(|v| *v == 1));
+    }
+
+    #[test]
+    fn wrapping_works() {
+        let mut m: EnergyMap = "900\n000\n000\n000".parse().unwrap();
+        m.rules.wrap = true;
+        assert_eq!(m.get_adjacent(0, 0).len(), 8);
+        assert!(m.get_adjacent(0, 0).contains(&(3, 2)));
+        m.step();
+        let expected: EnergyMap = "022\n222\n111\n222".parse().unwrap();
+        assert_eq!(m.map, expected.map);
+
+        // both sides of a two wide map are the same cell
+        let mut m: EnergyMap = "00".parse().unwrap();
+        m.rules.wrap = true;
+        assert_eq!(m.get_adjacent(0, 0), vec![(0, 1)]);
+        m.rules.wrap = false;
+        assert_eq!(m.get_adjacent(0, 0), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn step_events_works() {
+        let mut m: EnergyMap = "11111\n19991\n19191\n19991\n11111".parse().unwrap();
+        let events = m.step_events();
+        let flash = |row, col, depth| Flash { row, col, depth };
+        assert_eq!(
+            events,
+            vec![
+                flash(1, 1, 0),
+                flash(1, 2, 0),
+                flash(1, 3, 0),
+                flash(2, 1, 0),
+                flash(2, 3, 0),
+                flash(3, 1, 0),
+                flash(3, 2, 0),
+                flash(3, 3, 0),
+                flash(2, 2, 1),
+            ]
+        );
+
+        let s = include_str!("day11.test.txt");
+        let mut m: EnergyMap = s.parse().unwrap();
+        let counts: Vec<usize> = m.events().take(10).map(|e| e.len()).collect();
+        assert_eq!(counts.iter().sum::<usize>(), 204);
+        assert_eq!(counts[0], 0);
+
+        // every flash after the first of a cascade is next to an earlier, shallower one
+        let step: Vec<Flash> = m.events().find(|e| e.len() == 100).unwrap();
+        for (i, f) in step.iter().enumerate().filter(|(_, f)| f.depth > 0) {
+            assert!(step[..i].iter().any(|e| {
+                e.depth + 1 == f.depth && m.get_adjacent(e.row, e.col).contains(&(f.row, f.col))
+            }));
+        }
+    }
+
+    #[test]
+    fn animate_works() {
+        let mut m: EnergyMap = "11111\n19991\n19191\n19991\n11111".parse().unwrap();
+        let mut frames = vec![];
+        m.animate(1..3, |step, frame| {
+            frames.push((step, frame.to_string()));
+            Ok(())
+        })
+        .unwrap();
+
+        let f = |v: &str| format!("\x1b[1;93m{}\x1b[0m", v);
+        let zeros = f("0").repeat(3);
+        let expected = vec![
+            (
+                1,
+                format!(
+                    "Step 1\n34543\n4{}4\n5{}{}{}5\n4{}4\n34543\n",
+                    zeros,
+                    f("0"),
+                    f("0"),
+                    f("0"),
+                    zeros
+                ),
+            ),
+            (2, "Step 2\n45654\n51115\n61116\n51115\n45654\n".to_string()),
+        ];
+        assert_eq!(frames, expected);
+    }
+
+    #[test]
+    fn flat_map_matches_energy_map() {
+        // xorshift, so the grids are random but the same every run
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut random = move |n: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % n
+        };
+
+        let variants = [
+            Rules::default(),
+            Rules {
+                neighbourhood: Neighbourhood::VonNeumann,
+                wrap: true,
+                ..Rules::default()
+            },
+            Rules {
+                threshold: 12,
+                reset: 2,
+                step_increment: 2,
+                flash_increment: 3,
+                neighbourhood: Neighbourhood::Moore,
+                wrap: true,
+            },
+        ];
+        for rules in variants {
+            for _ in 0..5 {
+                let (height, width) = (1 + random(40) as usize, 1 + random(40) as usize);
+                let map = (0..height)
+                    .map(|_| (0..width).map(|_| random(10) as u8).collect())
+                    .collect();
+                let mut m = EnergyMap {
+                    map,
+                    rules: rules.clone(),
+                };
+                let mut flat = FlatMap::from(&m);
+                for step in 0..20 {
+                    assert_eq!(flat.step_in_bands(1 + step % 6), m.step());
+                    assert_eq!(flat, FlatMap::from(&m));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn large_cascade_works() {
+        // a flash in the corner sets off every other octopus
+        let mut map = vec![vec![8; 1000]; 1000];
+        map[0][0] = 9;
+        let mut m = EnergyMap {
+            map,
+            rules: Rules::default(),
+        };
+        assert_eq!(m.step(), 1_000_000);
+        assert!(m.map.iter().flatten().all(|v| *v == 0));
+    }
+
     #[test]
     fn part_one_simple() {
         let s = r#"11111
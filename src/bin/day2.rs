@@ -1,28 +1,143 @@
 #![feature(io_read_to_string)]
+use advent_of_code_2021::{report, Format};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::read_to_string;
 use std::num::ParseIntError;
 use std::str::FromStr;
+use std::time::Instant;
 
 fn main() {
     let mut f = File::open("day2.1.txt").unwrap();
     let data = read_to_string(&mut f).unwrap();
+    let (format, args) = Format::from_args();
+    let start = Instant::now();
     // Not sure why but there is an empty string at the end after the split operation
     let input = data.split('\n').collect::<Vec<_>>();
     let (x, y) = final_position(&input[..input.len() - 1]);
-    assert_eq!(x * y, 1840243);
-    println!("Final Position: ({}, {}) = {}", x, y, x * y);
+    report(
+        format,
+        (2, 1),
+        x * y,
+        Some(1840243),
+        start.elapsed(),
+        || println!("Final Position: ({}, {}) = {}", x, y, x * y),
+    );
 
     let mut f = File::open("day2.2.txt").unwrap();
     let data = read_to_string(&mut f).unwrap();
+    let start = Instant::now();
     let input = data.split('\n').collect::<Vec<_>>();
 
     // Not sure why but there is an empty string at the end after the split operation
     let (x, y) = final_position_2(&input[..input.len() - 1]);
-    assert_eq!(x * y, 1727785422);
-    println!("Final Position 2: ({}, {}) = {}", x, y, x * y);
+    report(
+        format,
+        (2, 2),
+        x * y,
+        Some(1727785422),
+        start.elapsed(),
+        || println!("Final Position 2: ({}, {}) = {}", x, y, x * y),
+    );
+
+    let mut constraints = Constraints::default();
+    let mut aim_model = false;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut number = |name: &str| -> i32 {
+            args.next()
+                .unwrap_or_else(|| panic!("{} requires a number", name))
+                .parse()
+                .unwrap()
+        };
+        match arg.as_ref() {
+            // limits and model used by the following --validate
+            "--max-depth" => constraints.max_depth = Some(number("--max-depth")),
+            "--max-aim" => constraints.max_aim = Some(number("--max-aim")),
+            "--submerged" => constraints.stay_submerged = true,
+            "--box" => {
+                constraints.bounds = Some(BoundingBox {
+                    min_x: number("--box"),
+                    max_x: number("--box"),
+                    min_depth: number("--box"),
+                    max_depth: number("--box"),
+                })
+            }
+            "--aim" => aim_model = true,
+            "--validate" => {
+                let path = args.next().expect("--validate requires a command file");
+                let s = read_to_string(&mut File::open(&path).unwrap()).unwrap();
+                let commands = compile_script(&s).unwrap_or_else(|e| script_failed(&path, e));
+                let result = if aim_model {
+                    validate(AimBased, &commands, &constraints)
+                } else {
+                    validate(Simple, &commands, &constraints)
+                };
+                match result {
+                    Ok(end) => println!("{}: ok, ending at {:?}", path, end),
+                    Err(v) => {
+                        println!("{}: {}", path, v);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--script" => {
+                let path = args.next().expect("--script requires a script file");
+                let s = read_to_string(&mut File::open(&path).unwrap()).unwrap();
+                let stmts = parse_script(&s).unwrap_or_else(|e| script_failed(&path, e));
+                // run the commands as they are produced, so long repeats need no memory
+                let (mut simple, mut aim) = (Submarine::new(Simple), Submarine::new(AimBased));
+                for command in ScriptCommands::new(&stmts) {
+                    let command = command.unwrap_or_else(|e| script_failed(&path, e));
+                    simple.execute(command);
+                    aim.execute(command);
+                }
+                println!("Simple: {:?}", simple.state);
+                println!("Aim based: {:?}", aim.state);
+            }
+            "--plan" => {
+                let mut number = |what: &str| -> i32 {
+                    args.next()
+                        .unwrap_or_else(|| panic!("--plan requires {}", what))
+                        .parse()
+                        .unwrap()
+                };
+                let (x, depth) = (number("an x"), number("a depth"));
+                let max_step = args.next().map(|s| s.parse().unwrap());
+                match plan_route(x, depth, max_step) {
+                    Some(commands) => commands.iter().for_each(|c| println!("{}", c)),
+                    None => {
+                        eprintln!("the step limit must be at least 1");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--trajectory" => {
+                let path = args.next().expect("--trajectory requires a command file");
+                let dir = args
+                    .next()
+                    .expect("--trajectory requires an output directory");
+                let s = read_to_string(&mut File::open(&path).unwrap()).unwrap();
+                let commands = compile_script(&s).unwrap_or_else(|e| script_failed(&path, e));
+                let simple = Trajectory::record(Simple, &commands);
+                let aim = Trajectory::record(AimBased, &commands);
+                std::fs::write(format!("{}/simple.csv", dir), simple.to_csv()).unwrap();
+                std::fs::write(format!("{}/aim.csv", dir), aim.to_csv()).unwrap();
+                let svg = plot_svg(&[("simple", &simple), ("aim based", &aim)]);
+                std::fs::write(format!("{}/trajectory.svg", dir), svg).unwrap();
+            }
+            _ => panic!("Unknown argument: {}", arg),
+        }
+    }
+}
+
+/// Report a script error against the file it came from and exit
+fn script_failed(path: &str, e: ScriptError) -> ! {
+    eprintln!("{}:{}", path, e);
+    std::process::exit(1);
 }
 
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 enum Command {
     Forward(i32),
     Back(i32),
@@ -44,30 +159,819 @@ impl FromStr for Command {
     }
 }
 
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Forward(v) => write!(f, "forward {}", v),
+            Self::Back(v) => write!(f, "backward {}", v),
+            Self::Up(v) => write!(f, "up {}", v),
+            Self::Down(v) => write!(f, "down {}", v),
+        }
+    }
+}
+
+/// Everything known about the submarine's position
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+struct State {
+    x: i32,
+    depth: i32,
+    aim: i32,
+}
+
+/// How a command moves the submarine. Any `Fn(State, Command) -> State` is a model as well.
+trait NavigationModel {
+    fn apply(&self, state: State, command: Command) -> State;
+}
+
+/// Part one's model: up and down change the depth directly and aim is unused
+struct Simple;
+
+impl NavigationModel for Simple {
+    fn apply(&self, s: State, command: Command) -> State {
+        match command {
+            Command::Forward(v) => State { x: s.x + v, ..s },
+            Command::Back(v) => State { x: s.x - v, ..s },
+            Command::Up(v) => State {
+                depth: s.depth - v,
+                ..s
+            },
+            Command::Down(v) => State {
+                depth: s.depth + v,
+                ..s
+            },
+        }
+    }
+}
+
+/// Part two's model: up and down change the aim, and moving changes depth along it. Going back
+/// retraces the same heading in reverse.
+struct AimBased;
+
+impl NavigationModel for AimBased {
+    fn apply(&self, s: State, command: Command) -> State {
+        match command {
+            Command::Forward(v) => State {
+                x: s.x + v,
+                depth: s.depth + s.aim * v,
+                ..s
+            },
+            Command::Back(v) => State {
+                x: s.x - v,
+                depth: s.depth - s.aim * v,
+                ..s
+            },
+            Command::Up(v) => State {
+                aim: s.aim - v,
+                ..s
+            },
+            Command::Down(v) => State {
+                aim: s.aim + v,
+                ..s
+            },
+        }
+    }
+}
+
+impl<F: Fn(State, Command) -> State> NavigationModel for F {
+    fn apply(&self, state: State, command: Command) -> State {
+        self(state, command)
+    }
+}
+
+struct Submarine<M: NavigationModel> {
+    model: M,
+    state: State,
+}
+
+impl<M: NavigationModel> Submarine<M> {
+    fn new(model: M) -> Self {
+        Self {
+            model,
+            state: State::default(),
+        }
+    }
+
+    /// Carry out a command, returning the state it leaves the submarine in
+    fn execute(&mut self, command: Command) -> State {
+        self.state = self.model.apply(self.state, command);
+        self.state
+    }
+
+    /// Carry out each command in turn, yielding the state after each one
+    fn run<'a, I: IntoIterator<Item = Command> + 'a>(
+        &'a mut self,
+        commands: I,
+    ) -> impl Iterator<Item = State> + 'a {
+        commands.into_iter().map(move |c| self.execute(c))
+    }
+}
+
+fn parse_commands<'a>(input: &'a [&str]) -> impl Iterator<Item = Command> + 'a {
+    input.iter().map(|s| s.parse::<Command>().unwrap())
+}
+
 fn final_position(input: &[&str]) -> (i32, i32) {
-    input.iter().map(|s| s.parse::<Command>().unwrap()).fold(
-        Default::default(),
-        |(x, y), command| match command {
-            Command::Forward(v) => (x + v, y),
-            Command::Back(v) => (x - v, y),
-            Command::Up(v) => (x, y - v),
-            Command::Down(v) => (x, y + v),
-        },
-    )
+    let mut sub = Submarine::new(Simple);
+    let end = sub.run(parse_commands(input)).last().unwrap_or_default();
+    (end.x, end.depth)
 }
 
 fn final_position_2(input: &[&str]) -> (i32, i32) {
-    let out: (i32, i32, i32) = input.iter().map(|s| s.parse::<Command>().unwrap()).fold(
-        Default::default(),
-        |(x, y, aim), command| match command {
-            Command::Forward(v) => (x + v, y + aim * v, aim),
-            Command::Up(v) => (x, y, aim - v),
-            Command::Down(v) => (x, y, aim + v),
-            Command::Back(_) => unreachable!(),
-        },
+    let mut sub = Submarine::new(AimBased);
+    let end = sub.run(parse_commands(input)).last().unwrap_or_default();
+    (end.x, end.depth)
+}
+
+/// Commands taking the aim based submarine from the start to `(x, depth)`. Every target can
+/// be reached; `None` means a `max_step` below one. Without a step limit the plan is as short
+/// as possible; with one, no command moves more than `max_step` but the plan may not be the
+/// shortest.
+fn plan_route(x: i32, depth: i32, max_step: Option<i32>) -> Option<Vec<Command>> {
+    if max_step.is_some_and(|l| l < 1) {
+        return None;
+    }
+    if x < 0 {
+        // backing up with the aim reversed changes depth just as going forward does
+        let mirrored = plan_route(-x, depth, max_step)?;
+        let mirror = |c| match c {
+            Command::Forward(v) => Command::Back(v),
+            Command::Back(v) => Command::Forward(v),
+            Command::Up(v) => Command::Down(v),
+            Command::Down(v) => Command::Up(v),
+        };
+        return Some(mirrored.into_iter().map(mirror).collect());
+    }
+    let tilt = |aim: i32| {
+        if aim < 0 {
+            Command::Up(-aim)
+        } else {
+            Command::Down(aim)
+        }
+    };
+    let limit = max_step.unwrap_or(i32::MAX);
+    // change the aim by `by`, no more than `limit` at a time
+    let tilt_by = |commands: &mut Vec<Command>, mut by: i32| {
+        while by != 0 {
+            let change = by.clamp(-limit, limit);
+            commands.push(tilt(change));
+            by -= change;
+        }
+    };
+    if x == 0 {
+        if depth == 0 {
+            return Some(vec![]);
+        }
+        // step out and back, with the aim changed in between
+        let mut commands = vec![Command::Forward(1)];
+        tilt_by(&mut commands, -depth);
+        commands.push(Command::Back(1));
+        return Some(commands);
+    }
+
+    let shortest = if depth == 0 {
+        vec![Command::Forward(x)]
+    } else if depth % x == 0 {
+        vec![tilt(depth / x), Command::Forward(x)]
+    } else {
+        // level until the last unit, then dive the whole way
+        vec![Command::Forward(x - 1), tilt(depth), Command::Forward(1)]
+    };
+    if shortest.iter().all(|c| match *c {
+        Command::Forward(v) | Command::Back(v) | Command::Up(v) | Command::Down(v) => v <= limit,
+    }) {
+        return Some(shortest);
+    }
+
+    // spread the depth as evenly as possible: x - r units at aim a, then r at aim a + 1
+    let (a, r) = (depth.div_euclid(x), depth.rem_euclid(x));
+    let mut commands = vec![];
+    let mut aim = 0;
+    for (target, units) in [(a, x - r), (a + 1, r)] {
+        if units == 0 {
+            continue;
+        }
+        tilt_by(&mut commands, target - aim);
+        aim = target;
+        let mut left = units;
+        while left > 0 {
+            commands.push(Command::Forward(left.min(limit)));
+            left -= left.min(limit);
+        }
+    }
+    Some(commands)
+}
+
+/// Every state a submarine passes through, starting with where it began
+#[derive(Debug)]
+struct Trajectory {
+    states: Vec<State>,
+}
+
+impl Trajectory {
+    fn record<M: NavigationModel>(model: M, commands: &[Command]) -> Self {
+        let mut states = vec![State::default()];
+        states.extend(Submarine::new(model).run(commands.iter().copied()));
+        Self { states }
+    }
+
+    fn to_csv(&self) -> String {
+        let mut out = "command,x,depth,aim\n".to_string();
+        for (i, s) in self.states.iter().enumerate() {
+            out.push_str(&format!("{},{},{},{}\n", i, s.x, s.depth, s.aim));
+        }
+        out
+    }
+}
+
+/// Plot named trajectories as depth against horizontal position on shared axes, as a standalone
+/// SVG document. Depth increases down the page.
+fn plot_svg(trajectories: &[(&str, &Trajectory)]) -> String {
+    const COLOURS: [&str; 4] = ["#1f77b4", "#d62728", "#2ca02c", "#9467bd"];
+    const WIDTH: f64 = 800.0;
+    const HEIGHT: f64 = 600.0;
+    const MARGIN: f64 = 60.0;
+
+    let states = || trajectories.iter().flat_map(|(_, t)| t.states.iter());
+    let min_x = states().map(|s| s.x).min().unwrap_or(0);
+    let max_x = states().map(|s| s.x).max().unwrap_or(0);
+    let min_depth = states().map(|s| s.depth).min().unwrap_or(0);
+    let max_depth = states().map(|s| s.depth).max().unwrap_or(0);
+    let span = |lo: i32, hi: i32| (hi - lo).max(1) as f64;
+    let px = |x: i32| MARGIN + (x - min_x) as f64 / span(min_x, max_x) * (WIDTH - 2.0 * MARGIN);
+    let py = |d: i32| {
+        MARGIN + (d - min_depth) as f64 / span(min_depth, max_depth) * (HEIGHT - 2.0 * MARGIN)
+    };
+
+    let text = |x: f64, y: f64, anchor: &str, fill: &str, label: &str| {
+        format!(
+            "<text x=\"{}\" y=\"{}\" text-anchor=\"{}\" fill=\"{}\">{}</text>\n",
+            x, y, anchor, fill, label
+        )
+    };
+
+    let mut out = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\n",
+        WIDTH, HEIGHT
     );
+    out.push_str("<rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n");
+    out.push_str("<g font-family=\"sans-serif\" font-size=\"12\">\n");
+
+    // axes along the top and left, labelled with their ranges
+    let (right, bottom) = (WIDTH - MARGIN, HEIGHT - MARGIN);
+    out.push_str(&format!(
+        "<polyline fill=\"none\" stroke=\"black\" points=\"{},{} {},{} {},{}\"/>\n",
+        right, MARGIN, MARGIN, MARGIN, MARGIN, bottom
+    ));
+    let top = MARGIN - 10.0;
+    out.push_str(&text(MARGIN, top, "start", "black", &min_x.to_string()));
+    out.push_str(&text(right, top, "end", "black", &max_x.to_string()));
+    out.push_str(&text(
+        WIDTH / 2.0,
+        top,
+        "middle",
+        "black",
+        "horizontal position",
+    ));
+    let left = MARGIN - 5.0;
+    out.push_str(&text(
+        left,
+        MARGIN + 4.0,
+        "end",
+        "black",
+        &min_depth.to_string(),
+    ));
+    out.push_str(&text(left, bottom, "end", "black", &max_depth.to_string()));
+    out.push_str(&text(left, HEIGHT / 2.0, "end", "black", "depth"));
+
+    for (i, (name, t)) in trajectories.iter().enumerate() {
+        let colour = COLOURS[i % COLOURS.len()];
+        let points: Vec<String> = t
+            .states
+            .iter()
+            .map(|s| format!("{:.1},{:.1}", px(s.x), py(s.depth)))
+            .collect();
+        out.push_str(&format!(
+            "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"2\" points=\"{}\"/>\n",
+            colour,
+            points.join(" ")
+        ));
+        let y = bottom + 20.0 + 15.0 * i as f64;
+        out.push_str(&text(right, y, "end", colour, name));
+    }
+    out.push_str("</g>\n");
+    out.push_str("</svg>\n");
+    out
+}
+
+/// Area the submarine has to stay inside
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct BoundingBox {
+    min_x: i32,
+    max_x: i32,
+    min_depth: i32,
+    max_depth: i32,
+}
+
+/// Operational limits on a route. Limits that are `None` or `false` aren't checked.
+#[derive(Debug, Default)]
+struct Constraints {
+    max_depth: Option<i32>,
+    /// Never rise above the surface at depth zero
+    stay_submerged: bool,
+    /// Largest aim allowed either way
+    max_aim: Option<i32>,
+    bounds: Option<BoundingBox>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Breach {
+    TooDeep,
+    Surfaced,
+    AimTooSteep,
+    OutOfBounds,
+}
+
+/// The first command that took a route outside its constraints
+#[derive(Debug, Eq, PartialEq)]
+struct Violation {
+    /// Index of the command in the route, from zero
+    index: usize,
+    command: Command,
+    state: State,
+    breach: Breach,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let what = match self.breach {
+            Breach::TooDeep => "went too deep",
+            Breach::Surfaced => "rose above the surface",
+            Breach::AimTooSteep => "aimed too steeply",
+            Breach::OutOfBounds => "left the bounding box",
+        };
+        write!(
+            f,
+            "command {} ({}) {} at x {}, depth {}, aim {}",
+            self.index + 1,
+            self.command,
+            what,
+            self.state.x,
+            self.state.depth,
+            self.state.aim
+        )
+    }
+}
+
+impl Constraints {
+    fn check(&self, s: State) -> Option<Breach> {
+        if self.max_depth.is_some_and(|max| s.depth > max) {
+            return Some(Breach::TooDeep);
+        }
+        if self.stay_submerged && s.depth < 0 {
+            return Some(Breach::Surfaced);
+        }
+        if self.max_aim.is_some_and(|max| s.aim.abs() > max) {
+            return Some(Breach::AimTooSteep);
+        }
+        if let Some(b) = self.bounds {
+            if s.x < b.min_x || s.x > b.max_x || s.depth < b.min_depth || s.depth > b.max_depth {
+                return Some(Breach::OutOfBounds);
+            }
+        }
+        None
+    }
+}
+
+/// Run a route through a model, stopping at the first command that breaks the constraints.
+/// Returns where the route ends if it stays within them.
+fn validate<M: NavigationModel>(
+    model: M,
+    commands: &[Command],
+    constraints: &Constraints,
+) -> Result<State, Violation> {
+    let mut sub = Submarine::new(model);
+    for (index, &command) in commands.iter().enumerate() {
+        let state = sub.execute(command);
+        if let Some(breach) = constraints.check(state) {
+            return Err(Violation {
+                index,
+                command,
+                state,
+                breach,
+            });
+        }
+    }
+    Ok(sub.state)
+}
+
+/// Problem in a command script. Lines and columns count from one.
+#[derive(Debug, Eq, PartialEq)]
+struct ScriptError {
+    line: usize,
+    column: usize,
+    message: String,
+}
+
+impl std::fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// Line and column of something in a script
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Pos {
+    line: usize,
+    column: usize,
+}
+
+impl Pos {
+    fn error<T>(self, message: String) -> Result<T, ScriptError> {
+        Err(ScriptError {
+            line: self.line,
+            column: self.column,
+            message,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Num(i32),
+    Ident(String),
+    Sym(char),
+    Newline,
+    Eof,
+}
+
+fn lex(s: &str) -> Result<Vec<(Tok, Pos)>, ScriptError> {
+    let mut tokens = vec![];
+    for (i, line) in s.lines().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        let mut col = 0;
+        while col < chars.len() {
+            let pos = Pos {
+                line: i + 1,
+                column: col + 1,
+            };
+            let c = chars[col];
+            if c == '#' {
+                // comment to the end of the line
+                break;
+            } else if c.is_whitespace() {
+                col += 1;
+            } else if c.is_ascii_digit() {
+                let start = col;
+                while col < chars.len() && chars[col].is_ascii_digit() {
+                    col += 1;
+                }
+                let digits: String = chars[start..col].iter().collect();
+                match digits.parse() {
+                    Ok(n) => tokens.push((Tok::Num(n), pos)),
+                    Err(_) => return pos.error(format!("number `{}` is too large", digits)),
+                }
+            } else if c.is_alphabetic() || c == '_' {
+                let start = col;
+                while col < chars.len() && (chars[col].is_alphanumeric() || chars[col] == '_') {
+                    col += 1;
+                }
+                tokens.push((Tok::Ident(chars[start..col].iter().collect()), pos));
+            } else if "+-*/(){}=".contains(c) {
+                tokens.push((Tok::Sym(c), pos));
+                col += 1;
+            } else {
+                return pos.error(format!("unexpected character `{}`", c));
+            }
+        }
+        tokens.push((
+            Tok::Newline,
+            Pos {
+                line: i + 1,
+                column: chars.len() + 1,
+            },
+        ));
+    }
+    let end = match tokens.last() {
+        Some((_, pos)) => *pos,
+        None => Pos { line: 1, column: 1 },
+    };
+    tokens.push((Tok::Eof, end));
+    Ok(tokens)
+}
+
+#[derive(Debug)]
+enum Expr {
+    Num(i32),
+    Var(String, Pos),
+    Neg(Box<Expr>, Pos),
+    /// Arithmetic on two operands, with the operator and its position
+    Op(Box<Expr>, char, Pos, Box<Expr>),
+}
+
+#[derive(Debug)]
+enum Stmt {
+    Let(String, Expr),
+    Command(fn(i32) -> Command, Expr),
+    /// Run the body a number of times, the count given with its position
+    Repeat(Expr, Pos, Vec<Stmt>),
+}
+
+struct Parser {
+    tokens: Vec<(Tok, Pos)>,
+    i: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Tok {
+        &self.tokens[self.i].0
+    }
+
+    fn pos(&self) -> Pos {
+        self.tokens[self.i].1
+    }
+
+    fn next(&mut self) -> (Tok, Pos) {
+        let t = self.tokens[self.i].clone();
+        if t.0 != Tok::Eof {
+            self.i += 1;
+        }
+        t
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ScriptError> {
+        match self.next() {
+            (Tok::Sym(s), _) if s == c => Ok(()),
+            (t, pos) => pos.error(format!("expected `{}`, found {}", c, describe(&t))),
+        }
+    }
+
+    /// Statements up to the end of the script, or the closing brace of a block
+    fn block(&mut self, in_braces: bool) -> Result<Vec<Stmt>, ScriptError> {
+        let mut stmts = vec![];
+        loop {
+            match self.peek() {
+                Tok::Newline => {
+                    self.next();
+                }
+                Tok::Eof if in_braces => return self.pos().error("expected `}`".to_string()),
+                Tok::Eof => return Ok(stmts),
+                Tok::Sym('}') if in_braces => {
+                    self.next();
+                    return Ok(stmts);
+                }
+                _ => {
+                    stmts.push(self.stmt()?);
+                    match self.peek() {
+                        Tok::Newline | Tok::Eof | Tok::Sym('}') => {}
+                        t => {
+                            return self
+                                .pos()
+                                .error(format!("expected end of line, found {}", describe(t)))
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn stmt(&mut self) -> Result<Stmt, ScriptError> {
+        let (word, pos) = match self.next() {
+            (Tok::Ident(word), pos) => (word, pos),
+            (t, pos) => return pos.error(format!("expected a command, found {}", describe(&t))),
+        };
+        match word.to_ascii_lowercase().as_ref() {
+            "let" => {
+                let name = match self.next() {
+                    (Tok::Ident(name), _) => name,
+                    (t, pos) => {
+                        return pos
+                            .error(format!("expected a variable name, found {}", describe(&t)))
+                    }
+                };
+                self.expect('=')?;
+                Ok(Stmt::Let(name, self.expr()?))
+            }
+            "repeat" => {
+                let pos = self.pos();
+                let count = self.expr()?;
+                self.expect('{')?;
+                Ok(Stmt::Repeat(count, pos, self.block(true)?))
+            }
+            "forward" => Ok(Stmt::Command(Command::Forward, self.expr()?)),
+            "backward" => Ok(Stmt::Command(Command::Back, self.expr()?)),
+            "up" => Ok(Stmt::Command(Command::Up, self.expr()?)),
+            "down" => Ok(Stmt::Command(Command::Down, self.expr()?)),
+            _ => pos.error(format!("unknown command `{}`", word)),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, ScriptError> {
+        let mut lhs = self.term()?;
+        while let Tok::Sym(op @ ('+' | '-')) = *self.peek() {
+            let (_, pos) = self.next();
+            lhs = Expr::Op(Box::new(lhs), op, pos, Box::new(self.term()?));
+        }
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Expr, ScriptError> {
+        let mut lhs = self.unary()?;
+        while let Tok::Sym(op @ ('*' | '/')) = *self.peek() {
+            let (_, pos) = self.next();
+            lhs = Expr::Op(Box::new(lhs), op, pos, Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ScriptError> {
+        match self.next() {
+            (Tok::Sym('-'), pos) => Ok(Expr::Neg(Box::new(self.unary()?), pos)),
+            (Tok::Sym('('), _) => {
+                let e = self.expr()?;
+                self.expect(')')?;
+                Ok(e)
+            }
+            (Tok::Num(n), _) => Ok(Expr::Num(n)),
+            (Tok::Ident(name), pos) => Ok(Expr::Var(name, pos)),
+            (t, pos) => pos.error(format!("expected a number, found {}", describe(&t))),
+        }
+    }
+}
+
+fn describe(t: &Tok) -> String {
+    match t {
+        Tok::Num(n) => format!("`{}`", n),
+        Tok::Ident(s) => format!("`{}`", s),
+        Tok::Sym(c) => format!("`{}`", c),
+        Tok::Newline => "end of line".to_string(),
+        Tok::Eof => "end of script".to_string(),
+    }
+}
+
+/// Parse a command script. Besides plain commands, scripts may have `#` comments,
+/// `let name = expr` variables, `repeat n { ... }` blocks and `+ - * /` arithmetic in arguments.
+fn parse_script(s: &str) -> Result<Vec<Stmt>, ScriptError> {
+    let mut parser = Parser {
+        tokens: lex(s)?,
+        i: 0,
+    };
+    parser.block(false)
+}
+
+fn eval(e: &Expr, vars: &HashMap<String, i32>) -> Result<i32, ScriptError> {
+    match e {
+        Expr::Num(n) => Ok(*n),
+        Expr::Var(name, pos) => match vars.get(name) {
+            Some(v) => Ok(*v),
+            None => pos.error(format!("unknown variable `{}`", name)),
+        },
+        Expr::Neg(e, pos) => match eval(e, vars)?.checked_neg() {
+            Some(v) => Ok(v),
+            None => pos.error("arithmetic overflow".to_string()),
+        },
+        Expr::Op(lhs, op, pos, rhs) => {
+            let (a, b) = (eval(lhs, vars)?, eval(rhs, vars)?);
+            let v = match op {
+                '+' => a.checked_add(b),
+                '-' => a.checked_sub(b),
+                '*' => a.checked_mul(b),
+                _ if b == 0 => return pos.error("division by zero".to_string()),
+                _ => a.checked_div(b),
+            };
+            match v {
+                Some(v) => Ok(v),
+                None => pos.error("arithmetic overflow".to_string()),
+            }
+        }
+    }
+}
+
+/// Most commands `compile_script` will expand a script into
+const MAX_COMMANDS: usize = 10_000_000;
+
+/// Most statements and repeat passes a script may run, so loops that produce no commands
+/// still end
+const MAX_STEPS: u64 = 50_000_000;
+
+/// Statements left to run at one level of a script
+struct Frame<'a> {
+    stmts: &'a [Stmt],
+    next: usize,
+    /// Further passes over `stmts` still to make
+    repeats: i32,
+    /// Where the repeat count was given, if this is a repeat body
+    pos: Option<Pos>,
+}
+
+/// The commands a script stands for, produced one at a time as the script runs. Stops after
+/// the first error.
+struct ScriptCommands<'a> {
+    frames: Vec<Frame<'a>>,
+    vars: HashMap<String, i32>,
+    steps: u64,
+    /// Steps allowed before giving up, `MAX_STEPS` unless changed
+    max_steps: u64,
+}
+
+impl<'a> ScriptCommands<'a> {
+    fn new(stmts: &'a [Stmt]) -> Self {
+        ScriptCommands {
+            frames: vec![Frame {
+                stmts,
+                next: 0,
+                repeats: 0,
+                pos: None,
+            }],
+            vars: HashMap::new(),
+            steps: 0,
+            max_steps: MAX_STEPS,
+        }
+    }
+
+    /// Position of the outermost repeat being run, if any
+    fn repeat_pos(&self) -> Option<Pos> {
+        self.frames.iter().find_map(|f| f.pos)
+    }
+
+    fn step(&mut self) -> Result<Option<Command>, ScriptError> {
+        while let Some(frame) = self.frames.last_mut() {
+            self.steps += 1;
+            if self.steps > self.max_steps {
+                let pos = frame.pos.unwrap_or(Pos { line: 1, column: 1 });
+                let pos = self.repeat_pos().unwrap_or(pos);
+                return pos.error(format!("script runs more than {} steps", self.max_steps));
+            }
+            if frame.next == frame.stmts.len() {
+                if frame.repeats > 0 {
+                    frame.repeats -= 1;
+                    frame.next = 0;
+                } else {
+                    self.frames.pop();
+                }
+                continue;
+            }
+            let stmt = &frame.stmts[frame.next];
+            frame.next += 1;
+            match stmt {
+                Stmt::Let(name, e) => {
+                    let v = eval(e, &self.vars)?;
+                    match self.vars.get_mut(name) {
+                        Some(old) => *old = v,
+                        None => {
+                            self.vars.insert(name.clone(), v);
+                        }
+                    }
+                }
+                Stmt::Command(command, e) => return Ok(Some(command(eval(e, &self.vars)?))),
+                Stmt::Repeat(count, pos, body) => {
+                    let n = eval(count, &self.vars)?;
+                    if n < 0 {
+                        return pos.error(format!("cannot repeat {} times", n));
+                    }
+                    if n > 0 && !body.is_empty() {
+                        self.frames.push(Frame {
+                            stmts: body,
+                            next: 0,
+                            repeats: n - 1,
+                            pos: Some(*pos),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Iterator for ScriptCommands<'_> {
+    type Item = Result<Command, ScriptError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.step();
+        if result.is_err() {
+            self.frames.clear();
+        }
+        result.transpose()
+    }
+}
 
-    (out.0, out.1)
+/// Parse a script and expand it into the commands it stands for, refusing to produce more
+/// than `MAX_COMMANDS`
+fn compile_script(s: &str) -> Result<Vec<Command>, ScriptError> {
+    let stmts = parse_script(s)?;
+    let mut commands = ScriptCommands::new(&stmts);
+    let mut out = vec![];
+    while let Some(command) = commands.next() {
+        if out.len() == MAX_COMMANDS {
+            let pos = commands.repeat_pos().unwrap_or(Pos { line: 1, column: 1 });
+            return pos.error(format!(
+                "script expands to more than {} commands",
+                MAX_COMMANDS
+            ));
+        }
+        out.push(command?);
+    }
+    Ok(out)
 }
 
 #[test]
@@ -103,3 +1007,220 @@ fn part_two() {
     assert_eq!(y, 60);
     assert_eq!(x * y, 900);
 }
+
+#[test]
+fn back_works_in_every_model() {
+    let input = vec!["down 2", "forward 3", "backward 1"];
+
+    let states: Vec<State> = Submarine::new(Simple).run(parse_commands(&input)).collect();
+    let state = |x, depth, aim| State { x, depth, aim };
+    assert_eq!(states, vec![state(0, 2, 0), state(3, 2, 0), state(2, 2, 0)]);
+
+    let states: Vec<State> = Submarine::new(AimBased)
+        .run(parse_commands(&input))
+        .collect();
+    assert_eq!(states, vec![state(0, 0, 2), state(3, 6, 2), state(2, 4, 2)]);
+}
+
+#[test]
+fn user_defined_model_works() {
+    // forward moves twice as far, everything else is ignored
+    let model = |s: State, c: Command| match c {
+        Command::Forward(v) => State {
+            x: s.x + 2 * v,
+            ..s
+        },
+        _ => s,
+    };
+    let mut sub = Submarine::new(model);
+    sub.execute(Command::Forward(3));
+    sub.execute(Command::Down(3));
+    assert_eq!(
+        sub.state,
+        State {
+            x: 6,
+            depth: 0,
+            aim: 0
+        }
+    );
+}
+
+#[test]
+fn script_works() {
+    let script = r#"
+# dive in stages
+let step = 2
+forward 5
+repeat 3 {
+    down step * (1 + 1)  # aim further each time
+    forward -step + 10 / 2
+}
+let step = step - 3
+backward -step
+"#;
+    let commands = compile_script(script).unwrap();
+    let mut expected = vec![Command::Forward(5)];
+    for _ in 0..3 {
+        expected.push(Command::Down(4));
+        expected.push(Command::Forward(3));
+    }
+    expected.push(Command::Back(1));
+    assert_eq!(commands, expected);
+
+    // commands can be taken one at a time without expanding the whole script
+    let stmts = parse_script("repeat 2000000000 {\n  forward 1\n  down 2\n}").unwrap();
+    let first: Vec<_> = ScriptCommands::new(&stmts)
+        .take(3)
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(
+        first,
+        vec![Command::Forward(1), Command::Down(2), Command::Forward(1)]
+    );
+
+    // a plain command file is a script too
+    let plain = "forward 5\ndown 5\nforward 8\nup 3\ndown 8\nforward 2\n";
+    let end = Submarine::new(AimBased)
+        .run(compile_script(plain).unwrap())
+        .last()
+        .unwrap();
+    assert_eq!((end.x, end.depth), (15, 60));
+}
+
+#[test]
+fn script_errors_point_to_the_problem() {
+    let error = |s| compile_script(s).unwrap_err().to_string();
+    assert_eq!(
+        error("forward 1\nsideways 2"),
+        "2:1: unknown command `sideways`"
+    );
+    assert_eq!(
+        error("forward 1 +"),
+        "1:12: expected a number, found end of line"
+    );
+    assert_eq!(error("repeat 2 {\n  up 1\n"), "2:7: expected `}`");
+    assert_eq!(error("down x"), "1:6: unknown variable `x`");
+    assert_eq!(error("let y = 4\nup y / (y - 4)"), "2:6: division by zero");
+    assert_eq!(error("repeat -1 { up 1 }"), "1:8: cannot repeat -1 times");
+    assert_eq!(
+        error("up 1 down 2"),
+        "1:6: expected end of line, found `down`"
+    );
+    assert_eq!(error("up 1;"), "1:5: unexpected character `;`");
+    assert_eq!(
+        error("up 1\nrepeat 2000000000 {\n  repeat 1 { forward 1 }\n}"),
+        "2:8: script expands to more than 10000000 commands"
+    );
+
+    // loops without commands are limited too
+    let stmts = parse_script("let x = 0\nrepeat 2000000000 {\n  let x = x + 1\n}").unwrap();
+    let mut commands = ScriptCommands::new(&stmts);
+    commands.max_steps = 1000;
+    let error = commands.next().unwrap().unwrap_err();
+    assert_eq!(error.to_string(), "2:8: script runs more than 1000 steps");
+    assert!(commands.next().is_none());
+}
+
+#[test]
+fn trajectory_works() {
+    let commands = compile_script("forward 5\ndown 5\nforward 8").unwrap();
+    let t = Trajectory::record(AimBased, &commands);
+    assert_eq!(t.states.len(), 4);
+    assert_eq!(
+        t.to_csv(),
+        "command,x,depth,aim\n0,0,0,0\n1,5,0,0\n2,5,0,5\n3,13,40,5\n"
+    );
+
+    let svg = plot_svg(&[("aim", &t)]);
+    assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+    assert!(svg.ends_with("</svg>\n"));
+    // the dive ends in the bottom right corner of the plot
+    assert!(svg.contains("points=\"60.0,60.0 321.5,60.0 321.5,60.0 740.0,540.0\""));
+}
+
+#[test]
+fn validate_works() {
+    let commands = compile_script("forward 5\ndown 5\nforward 8\nup 3\ndown 8\nforward 2").unwrap();
+
+    let end = validate(AimBased, &commands, &Constraints::default()).unwrap();
+    assert_eq!((end.x, end.depth), (15, 60));
+
+    let constraints = Constraints {
+        max_depth: Some(50),
+        ..Constraints::default()
+    };
+    let v = validate(AimBased, &commands, &constraints).unwrap_err();
+    assert_eq!(v.index, 5);
+    assert_eq!(v.breach, Breach::TooDeep);
+    assert_eq!(
+        v.to_string(),
+        "command 6 (forward 2) went too deep at x 15, depth 60, aim 10"
+    );
+
+    let constraints = Constraints {
+        max_aim: Some(8),
+        ..Constraints::default()
+    };
+    let v = validate(AimBased, &commands, &constraints).unwrap_err();
+    assert_eq!((v.index, v.breach), (4, Breach::AimTooSteep));
+
+    let constraints = Constraints {
+        stay_submerged: true,
+        ..Constraints::default()
+    };
+    let v = validate(Simple, &[Command::Down(1), Command::Up(2)], &constraints).unwrap_err();
+    assert_eq!((v.index, v.breach), (1, Breach::Surfaced));
+
+    let constraints = Constraints {
+        bounds: Some(BoundingBox {
+            min_x: 0,
+            max_x: 10,
+            min_depth: 0,
+            max_depth: 100,
+        }),
+        ..Constraints::default()
+    };
+    let v = validate(Simple, &commands, &constraints).unwrap_err();
+    assert_eq!((v.index, v.breach), (2, Breach::OutOfBounds));
+}
+
+#[test]
+fn planned_routes_reach_their_target() {
+    for max_step in [None, Some(1), Some(3), Some(20)] {
+        for x in -25..25 {
+            for depth in -60..60 {
+                let commands = plan_route(x, depth, max_step).unwrap();
+                let lines: Vec<_> = commands.iter().map(|c| c.to_string()).collect();
+                let lines: Vec<_> = lines.iter().map(|s| s.as_ref()).collect();
+                assert_eq!(final_position_2(&lines), (x, depth));
+                if let Some(limit) = max_step {
+                    assert!(lines.iter().all(|l| {
+                        let v: i32 = l.split(' ').nth(1).unwrap().parse().unwrap();
+                        (1..=limit).contains(&v)
+                    }));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn planned_routes_are_shortest() {
+    assert_eq!(plan_route(0, 0, None).unwrap(), vec![]);
+    assert_eq!(plan_route(7, 0, None).unwrap().len(), 1);
+    assert_eq!(
+        plan_route(5, -15, None).unwrap(),
+        vec![Command::Up(3), Command::Forward(5)]
+    );
+    assert_eq!(plan_route(15, 60, None).unwrap().len(), 2);
+    assert_eq!(plan_route(15, 61, None).unwrap().len(), 3);
+    assert_eq!(
+        plan_route(-3, -6, None).unwrap(),
+        vec![Command::Down(2), Command::Back(3)]
+    );
+    assert_eq!(
+        plan_route(0, 4, None).unwrap(),
+        vec![Command::Forward(1), Command::Up(4), Command::Back(1)]
+    );
+    assert_eq!(plan_route(3, 4, Some(0)), None);
+}
@@ -1,16 +1,26 @@
 #![feature(io_read_to_string)]
+use advent_of_code_2021::{report, Format};
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::read_to_string;
+use std::time::Instant;
 
 fn main() {
     let mut f = File::open("day3.1.txt").unwrap();
     let s = read_to_string(&mut f).unwrap();
-    let s = s.split_whitespace().collect::<Vec<_>>();
 
+    let (format, _) = Format::from_args();
+    let start = Instant::now();
+    let s = s.split_whitespace().collect::<Vec<_>>();
     let (g, e) = calc_part_one(&s);
-    assert_eq!(g * e, 3_687_446);
-    println!("Answer Part 1: {}", g * e);
+    report(
+        format,
+        (3, 1),
+        g * e,
+        Some(3_687_446),
+        start.elapsed(),
+        || println!("Answer Part 1: {}", g * e),
+    );
 }
 
 fn calc_part_one(input: &[&str]) -> (u64, u64) {
@@ -1,21 +1,29 @@
 #![feature(io_read_to_string)]
 
+use advent_of_code_2021::{report, Format};
 use std::fs::File;
 use std::io::read_to_string;
 use std::str::FromStr;
+use std::time::Instant;
 
 fn main() {
     let mut f = File::open("day4.1.txt").unwrap();
     let s = read_to_string(&mut f).unwrap();
 
+    let (format, _) = Format::from_args();
+    let start = Instant::now();
     let bs: BingoSolver = s.parse().unwrap();
     let score = bs.first_winner();
-    assert_eq!(score, 58374);
-    println!("Part one: {}", score);
+    report(format, (4, 1), score, Some(58374), start.elapsed(), || {
+        println!("Part one: {}", score)
+    });
 
+    let start = Instant::now();
+    let bs: BingoSolver = s.parse().unwrap();
     let score = bs.last_winner();
-    assert_eq!(score, 11377);
-    println!("Part two: {}", score);
+    report(format, (4, 2), score, Some(11377), start.elapsed(), || {
+        println!("Part two: {}", score)
+    });
 }
 
 type Score = u32;
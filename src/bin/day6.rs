@@ -1,22 +1,39 @@
 #![feature(io_read_to_string)]
+use advent_of_code_2021::{report, Format};
 use std::fs::File;
 use std::io::read_to_string;
 use std::str::FromStr;
+use std::time::Instant;
 
 fn main() {
     let mut f = File::open("day6.1.txt").unwrap();
     let s = read_to_string(&mut f).unwrap();
     let input = &s[..s.len() - 1];
 
+    let (format, _) = Format::from_args();
+    let start = Instant::now();
     let mut school: School = input.parse().unwrap();
     school.pass_time(80);
-    println!("Part one: {}", school.total());
-    assert_eq!(school.total(), 372984);
+    report(
+        format,
+        (6, 1),
+        school.total(),
+        Some(372984),
+        start.elapsed(),
+        || println!("Part one: {}", school.total()),
+    );
 
+    let start = Instant::now();
     let mut school: School = input.parse().unwrap();
     school.pass_time(256);
-    println!("Part two: {}", school.total());
-    assert_eq!(school.total(), 1681503251694);
+    report(
+        format,
+        (6, 2),
+        school.total(),
+        Some(1681503251694),
+        start.elapsed(),
+        || println!("Part two: {}", school.total()),
+    );
 }
 
 /// School of lantern fish
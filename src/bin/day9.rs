@@ -1,56 +1,124 @@
 #![feature(io_read_to_string)]
+use advent_of_code_2021::{report, Format};
+use std::collections::HashSet;
 use std::fs::File;
-use std::io::read_to_string;
+use std::hash::Hash;
+use std::io::{read_to_string, BufRead, BufReader, Write};
+use std::time::Instant;
 
 fn main() {
     let mut f = File::open("day9.1.txt").unwrap();
     let s = read_to_string(&mut f).unwrap();
+    let (format, args) = Format::from_args();
+    let start = Instant::now();
     let hm: HeightMap = s.parse().unwrap();
-
     let risk = hm.risk_factor();
-    assert_eq!(risk, 514);
-    println!("Part one: {}", risk);
+    report(format, (9, 1), risk, Some(514), start.elapsed(), || {
+        println!("Part one: {}", risk)
+    });
 
+    let start = Instant::now();
+    let hm: HeightMap = s.parse().unwrap();
     let score = hm.basin_score();
-    assert_eq!(score, 1103130);
-    println!("Part two: {}", score);
-}
+    report(
+        format,
+        (9, 2),
+        score,
+        Some(1103130),
+        start.elapsed(),
+        || println!("Part two: {}", score),
+    );
 
-struct HeightMap {
-    hm: Vec<Vec<u32>>,
+    let mut barrier = 9;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--render" => print!("{}", hm.render()),
+            "--pgm" => {
+                let path = args.next().expect("--pgm requires an output path");
+                let mut f = File::create(path).unwrap();
+                hm.write_pgm(&mut f, 4).unwrap();
+            }
+            "--ppm" => {
+                let path = args.next().expect("--ppm requires an output path");
+                let mut f = File::create(path).unwrap();
+                hm.write_ppm(&mut f, 4).unwrap();
+            }
+            "--barrier" => {
+                barrier = args
+                    .next()
+                    .expect("--barrier requires a height")
+                    .parse()
+                    .unwrap();
+            }
+            "--voxel" => {
+                let path = args.next().expect("--voxel requires an input path");
+                let s = read_to_string(&mut File::open(path).unwrap()).unwrap();
+                let mut vm: VoxelMap = s.parse().unwrap();
+                vm.barrier = barrier;
+                println!("Voxel risk factor: {}", vm.risk_factor());
+                println!("Voxel basin score: {}", vm.basin_score());
+            }
+            "--stream" => {
+                let path = args.next().expect("--stream requires an input path");
+                let f = BufReader::new(File::open(path).unwrap());
+                println!("Risk factor: {}", streaming_risk_factor(f).unwrap());
+            }
+            _ => panic!("Unknown argument: {}", arg),
+        }
+    }
 }
 
-impl HeightMap {
+/// Colours cycled through when labelling basins
+const PALETTE: [(u8, u8, u8); 8] = [
+    (230, 25, 75),
+    (60, 180, 75),
+    (255, 225, 25),
+    (0, 130, 200),
+    (245, 130, 48),
+    (145, 30, 180),
+    (70, 240, 240),
+    (240, 50, 230),
+];
+
+/// Scoring shared by height maps of any dimension. Implementors describe their cells and how
+/// they connect; low points, risk and basins follow from that.
+trait HeightField {
+    type Point: Copy + Eq + Hash;
+
+    fn points(&self) -> Vec<Self::Point>;
+    fn height(&self, p: Self::Point) -> u8;
+    fn neighbours(&self, p: Self::Point) -> Vec<Self::Point>;
+    /// Cells at or above this height wall off basins
+    fn barrier(&self) -> u8;
+
+    fn is_low_point(&self, p: Self::Point) -> bool {
+        let height = self.height(p);
+        self.neighbours(p)
+            .into_iter()
+            .all(|n| height < self.height(n))
+    }
+
+    fn low_points(&self) -> Vec<Self::Point> {
+        self.points()
+            .into_iter()
+            .filter(|p| self.is_low_point(*p))
+            .collect()
+    }
+
     fn risk_factor(&self) -> u32 {
-        let mut risk = 0;
-        for row in 0..self.hm.len() {
-            for col in 0..self.hm[0].len() {
-                if self.is_low_point(row, col) {
-                    risk += self.hm[row][col] + 1;
-                }
-            }
-        }
-        risk
+        self.low_points()
+            .into_iter()
+            .map(|p| self.height(p) as u32 + 1)
+            .sum()
     }
 
     fn basin_score(&self) -> u32 {
-        // collect low points
-        let mut low_points = vec![];
-        for row in 0..self.hm.len() {
-            for col in 0..self.hm[0].len() {
-                if self.is_low_point(row, col) {
-                    low_points.push((row, col))
-                }
-            }
-        }
-
         // map low points to basin sizes
-        let mut scores: Vec<u32> = low_points
+        let mut scores: Vec<u32> = self
+            .low_points()
             .into_iter()
-            .map(|p| {
-                let mut visited = vec![];
-                self.basin_size(p.0, p.1, &mut visited)
-            })
+            .map(|p| self.basin_size(p))
             .collect();
 
         // return product of top 3 scores
@@ -63,26 +131,146 @@ impl HeightMap {
             .unwrap()
     }
 
-    fn basin_size(&self, row: usize, col: usize, inspected: &mut Vec<(usize, usize)>) -> u32 {
-        if inspected.contains(&(row, col)) {
-            return 0;
+    /// Number of cells reachable from `p` without crossing a barrier
+    fn basin_size(&self, p: Self::Point) -> u32 {
+        let mut inspected = HashSet::new();
+        let mut stack = vec![p];
+        let mut size = 0;
+        while let Some(p) = stack.pop() {
+            if !inspected.insert(p) || self.height(p) >= self.barrier() {
+                continue;
+            }
+            size += 1;
+            stack.extend(self.neighbours(p));
         }
-        inspected.push((row, col));
-        if self.hm[row][col] == 9 {
-            return 0;
+        size
+    }
+}
+
+struct HeightMap {
+    hm: Vec<Vec<u8>>,
+}
+
+impl HeightField for HeightMap {
+    type Point = (usize, usize);
+
+    fn points(&self) -> Vec<Self::Point> {
+        (0..self.hm.len())
+            .flat_map(|row| (0..self.hm[0].len()).map(move |col| (row, col)))
+            .collect()
+    }
+
+    fn height(&self, (row, col): Self::Point) -> u8 {
+        self.hm[row][col]
+    }
+
+    fn neighbours(&self, (row, col): Self::Point) -> Vec<Self::Point> {
+        self.surrounding_indicies(row, col)
+    }
+
+    fn barrier(&self) -> u8 {
+        9
+    }
+}
+
+impl HeightMap {
+    /// Label every cell with the index of the basin it drains into. Barrier cells belong to no
+    /// basin.
+    fn basin_labels(&self) -> Vec<Vec<Option<usize>>> {
+        let mut labels = vec![vec![None; self.hm[0].len()]; self.hm.len()];
+        let mut basin = 0;
+        for row in 0..self.hm.len() {
+            for col in 0..self.hm[0].len() {
+                if !self.is_low_point((row, col)) {
+                    continue;
+                }
+
+                let mut stack = vec![(row, col)];
+                while let Some((r, c)) = stack.pop() {
+                    if labels[r][c].is_some() || self.hm[r][c] >= self.barrier() {
+                        continue;
+                    }
+                    labels[r][c] = Some(basin);
+                    stack.extend(self.surrounding_indicies(r, c));
+                }
+                basin += 1;
+            }
         }
+        labels
+    }
 
-        let mut sum = 1;
-        for point in self.surrounding_indicies(row, col) {
-            sum += self.basin_size(point.0, point.1, inspected);
+    /// Render the map with ANSI colours, one colour per basin. Low points are shown in reverse
+    /// video and cells of height 9 are left uncoloured.
+    fn render(&self) -> String {
+        let labels = self.basin_labels();
+        let mut out = String::new();
+        for (row, inner) in self.hm.iter().enumerate() {
+            for (col, v) in inner.iter().enumerate() {
+                match labels[row][col] {
+                    Some(label) => {
+                        let (r, g, b) = PALETTE[label % PALETTE.len()];
+                        let invert = if self.is_low_point((row, col)) {
+                            "7;"
+                        } else {
+                            ""
+                        };
+                        out.push_str(&format!(
+                            "\x1b[{}38;2;{};{};{}m{}\x1b[0m",
+                            invert, r, g, b, v
+                        ));
+                    }
+                    None => out.push_str(&format!("\x1b[2m{}\x1b[0m", v)),
+                }
+            }
+            out.push('\n');
         }
-        sum
+        out
     }
 
-    fn is_low_point(&self, row: usize, col: usize) -> bool {
-        self.surrounding_points(row, col)
-            .into_iter()
-            .all(|v| self.hm[row][col] < v)
+    /// Write the heights as a binary greyscale PGM image, each cell drawn as a `scale` x `scale`
+    /// square.
+    fn write_pgm<W: Write>(&self, w: &mut W, scale: usize) -> std::io::Result<()> {
+        self.write_image(w, scale, "P5", |row, col| {
+            vec![(self.hm[row][col] as u32 * 255 / 9) as u8]
+        })
+    }
+
+    /// Write the basin labels as a binary PPM image, each cell drawn as a `scale` x `scale`
+    /// square. Low points are white and cells of height 9 are black.
+    fn write_ppm<W: Write>(&self, w: &mut W, scale: usize) -> std::io::Result<()> {
+        let labels = self.basin_labels();
+        self.write_image(w, scale, "P6", |row, col| match labels[row][col] {
+            Some(_) if self.is_low_point((row, col)) => vec![255, 255, 255],
+            Some(label) => {
+                let (r, g, b) = PALETTE[label % PALETTE.len()];
+                vec![r, g, b]
+            }
+            None => vec![0, 0, 0],
+        })
+    }
+
+    fn write_image<W: Write, F: Fn(usize, usize) -> Vec<u8>>(
+        &self,
+        w: &mut W,
+        scale: usize,
+        magic: &str,
+        pixel: F,
+    ) -> std::io::Result<()> {
+        let height = self.hm.len();
+        let width = self.hm[0].len();
+        write!(w, "{}\n{} {}\n255\n", magic, width * scale, height * scale)?;
+        for row in 0..height {
+            let line: Vec<u8> = (0..width)
+                .flat_map(|col| {
+                    let p = pixel(row, col);
+                    std::iter::repeat_n(p, scale).flatten()
+                })
+                .collect();
+            for _ in 0..scale {
+                w.write_all(&line)?;
+            }
+        }
+        Ok(())
     }
 
     fn surrounding_indicies(&self, row: usize, col: usize) -> Vec<(usize, usize)> {
@@ -106,30 +294,140 @@ impl HeightMap {
         }
         v
     }
-
-    fn surrounding_points(&self, row: usize, col: usize) -> Vec<u32> {
-        self.surrounding_indicies(row, col)
-            .into_iter()
-            .map(|(i, j)| self.hm[i][j])
-            .collect()
-    }
 }
 
 impl std::str::FromStr for HeightMap {
     type Err = ();
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let hm = s
-            .lines()
-            .map(|line| {
-                line.chars()
-                    .map(|c| c.to_digit(10).expect("Could not parse digit"))
-                    .collect()
-            })
-            .collect();
+        let hm = s.lines().map(parse_row).collect();
         Ok(Self { hm })
     }
 }
 
+/// Stacked layers of height maps, where each cell touches the six cells beside, above and below
+/// it
+struct VoxelMap {
+    vm: Vec<Vec<Vec<u8>>>,
+    barrier: u8,
+}
+
+impl HeightField for VoxelMap {
+    type Point = (usize, usize, usize);
+
+    fn points(&self) -> Vec<Self::Point> {
+        let mut v = vec![];
+        for layer in 0..self.vm.len() {
+            for row in 0..self.vm[0].len() {
+                for col in 0..self.vm[0][0].len() {
+                    v.push((layer, row, col));
+                }
+            }
+        }
+        v
+    }
+
+    fn height(&self, (layer, row, col): Self::Point) -> u8 {
+        self.vm[layer][row][col]
+    }
+
+    fn neighbours(&self, (layer, row, col): Self::Point) -> Vec<Self::Point> {
+        let mut v = vec![];
+        // push layers above and below
+        if layer > 0 {
+            v.push((layer - 1, row, col));
+        }
+        if layer < self.vm.len() - 1 {
+            v.push((layer + 1, row, col));
+        }
+
+        // push upper and lower
+        if row > 0 {
+            v.push((layer, row - 1, col));
+        }
+        if row < self.vm[0].len() - 1 {
+            v.push((layer, row + 1, col));
+        }
+
+        // push left and right
+        if col > 0 {
+            v.push((layer, row, col - 1));
+        }
+        if col < self.vm[0][0].len() - 1 {
+            v.push((layer, row, col + 1));
+        }
+        v
+    }
+
+    fn barrier(&self) -> u8 {
+        self.barrier
+    }
+}
+
+impl std::str::FromStr for VoxelMap {
+    type Err = ();
+    /// Layers are separated by one or more blank lines and must all have the same shape
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut vm: Vec<Vec<Vec<u8>>> = vec![];
+        let mut layer = vec![];
+        for line in s.lines().map(str::trim).chain([""]) {
+            if !line.is_empty() {
+                layer.push(parse_row(line));
+            } else if !layer.is_empty() {
+                vm.push(std::mem::take(&mut layer));
+            }
+        }
+        let shape = |layer: &Vec<Vec<u8>>| (layer.len(), layer[0].len());
+        if let Some(first) = vm.first() {
+            let ragged = |layer: &Vec<Vec<u8>>| layer.iter().any(|row| row.len() != layer[0].len());
+            if vm.iter().any(|l| ragged(l) || shape(l) != shape(first)) {
+                return Err(());
+            }
+        }
+        Ok(Self { vm, barrier: 9 })
+    }
+}
+
+fn parse_row(line: &str) -> Vec<u8> {
+    line.chars()
+        .map(|c| c.to_digit(10).expect("Could not parse digit") as u8)
+        .collect()
+}
+
+/// Compute the risk factor of a height map read line by line, keeping at most three rows in
+/// memory at once. Gives the same answer as `HeightMap::risk_factor`.
+fn streaming_risk_factor<R: BufRead>(reader: R) -> std::io::Result<u32> {
+    let mut lines = reader.lines();
+    let mut current = match lines.next() {
+        Some(line) => parse_row(&line?),
+        None => return Ok(0),
+    };
+    let mut above: Option<Vec<u8>> = None;
+    let mut risk = 0;
+    loop {
+        let below = lines.next().transpose()?.map(|line| parse_row(&line));
+        risk += row_risk(above.as_deref(), &current, below.as_deref());
+        match below {
+            Some(below) => above = Some(std::mem::replace(&mut current, below)),
+            None => return Ok(risk),
+        }
+    }
+}
+
+/// Risk of the low points in `row`, given the rows directly above and below it
+fn row_risk(above: Option<&[u8]>, row: &[u8], below: Option<&[u8]>) -> u32 {
+    let mut risk = 0;
+    for (col, &v) in row.iter().enumerate() {
+        let left = col.checked_sub(1).map(|c| row[c]);
+        let right = row.get(col + 1).copied();
+        let up = above.map(|r| r[col]);
+        let down = below.map(|r| r[col]);
+        if [left, right, up, down].into_iter().flatten().all(|n| v < n) {
+            risk += v as u32 + 1;
+        }
+    }
+    risk
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -148,12 +446,84 @@ mod tests {
 919
 999"#;
         let hm: HeightMap = s.parse().unwrap();
-        let mut visited = vec![];
-        let size = hm.basin_size(1, 1, &mut visited);
+        let size = hm.basin_size((1, 1));
         dbg!(hm.hm[1][1]);
         assert_eq!(size, 1);
     }
 
+    #[test]
+    fn basin_labels_works() {
+        let s = include_str!("day9.test.txt");
+        let hm: HeightMap = s.parse().unwrap();
+        let labels = hm.basin_labels();
+        let mut sizes = vec![0; 4];
+        for label in labels.iter().flatten().flatten() {
+            sizes[*label] += 1;
+        }
+        assert_eq!(sizes, vec![3, 9, 14, 9]);
+        assert_eq!(labels[0][2], None);
+    }
+
+    #[test]
+    fn write_ppm_works() {
+        let s = r#"919
+999"#;
+        let hm: HeightMap = s.parse().unwrap();
+        let mut out = vec![];
+        hm.write_ppm(&mut out, 1).unwrap();
+        let mut expected = b"P6\n3 2\n255\n".to_vec();
+        expected.extend([0, 0, 0, 255, 255, 255, 0, 0, 0]);
+        expected.extend([0; 9]);
+        assert_eq!(out, expected);
+
+        let mut out = vec![];
+        hm.write_pgm(&mut out, 2).unwrap();
+        assert!(out.starts_with(b"P5\n6 4\n255\n"));
+        assert_eq!(out.len(), b"P5\n6 4\n255\n".len() + 24);
+    }
+
+    #[test]
+    fn streaming_risk_factor_works() {
+        let s = include_str!("day9.test.txt");
+        assert_eq!(streaming_risk_factor(s.as_bytes()).unwrap(), 15);
+
+        let s = include_str!("../../day9.1.txt");
+        let hm: HeightMap = s.parse().unwrap();
+        assert_eq!(
+            streaming_risk_factor(s.as_bytes()).unwrap(),
+            hm.risk_factor()
+        );
+
+        assert_eq!(streaming_risk_factor("5".as_bytes()).unwrap(), 6);
+        assert_eq!(streaming_risk_factor("".as_bytes()).unwrap(), 0);
+    }
+
+    #[test]
+    fn voxel_map_works() {
+        let s = "999\n999\n999\n\n919\n959\n999\n\n999\n929\n999";
+        let vm: VoxelMap = s.parse().unwrap();
+        assert_eq!(vm.low_points(), vec![(1, 0, 1), (2, 1, 1)]);
+        assert_eq!(vm.risk_factor(), 5);
+        assert_eq!(vm.basin_size((1, 0, 1)), 3);
+
+        // extra blank lines don't make empty layers, but mismatched shapes are rejected
+        let s = "\n999\n919\n\n\n\n999\n999\n\n";
+        let vm: VoxelMap = s.parse().unwrap();
+        assert_eq!(vm.risk_factor(), 2);
+        assert!("999\n919\n\n99\n99".parse::<VoxelMap>().is_err());
+        assert!("999\n91\n\n999\n999".parse::<VoxelMap>().is_err());
+
+        // a single layer behaves like a height map
+        let s = include_str!("day9.test.txt");
+        let mut vm: VoxelMap = s.parse().unwrap();
+        assert_eq!(vm.risk_factor(), 15);
+        assert_eq!(vm.basin_score(), 1134);
+
+        assert_eq!(vm.basin_size((0, 0, 1)), 3);
+        vm.barrier = 3;
+        assert_eq!(vm.basin_size((0, 0, 1)), 2);
+    }
+
     #[test]
     fn part_one_works() {
         let s = include_str!("day9.test.txt");
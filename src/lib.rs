@@ -1 +1,174 @@
+use std::fmt::{Debug, Display};
+use std::time::Duration;
 
+/// How the binaries report their answers, chosen with `--format text|json`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl Format {
+    /// Take `--format` out of the command line, returning the format and the arguments left for
+    /// the binary. JSON covers only the answers, so it can't be combined with other flags.
+    pub fn from_args() -> (Self, Vec<String>) {
+        Self::from_args_allowing(&[])
+    }
+
+    /// Like `from_args`, but JSON may also be combined with `json_flags`, which the binary
+    /// prints as JSON itself
+    pub fn from_args_allowing(json_flags: &[&str]) -> (Self, Vec<String>) {
+        Self::parse(std::env::args().skip(1), json_flags)
+    }
+
+    fn parse<I: Iterator<Item = String>>(mut args: I, json_flags: &[&str]) -> (Self, Vec<String>) {
+        let mut format = Format::Text;
+        let mut rest = vec![];
+        while let Some(arg) = args.next() {
+            if arg != "--format" {
+                rest.push(arg);
+                continue;
+            }
+            format = match args.next().as_deref() {
+                Some("text") => Format::Text,
+                Some("json") => Format::Json,
+                f => panic!("Unknown format: {:?}", f),
+            };
+        }
+        let text_only = |a: &&String| a.starts_with("--") && !json_flags.contains(&a.as_str());
+        if format == Format::Json {
+            if let Some(flag) = rest.iter().find(text_only) {
+                panic!("--format json can't be used with {}", flag);
+            }
+        }
+        (format, rest)
+    }
+}
+
+/// One solved part of a day
+#[derive(Debug)]
+pub struct Run {
+    pub day: u32,
+    pub part: u32,
+    pub answer: String,
+    /// `None` until the day's answer is known
+    pub expected: Option<String>,
+    pub elapsed: Duration,
+}
+
+impl Run {
+    pub fn status(&self) -> &'static str {
+        match &self.expected {
+            None => "unknown",
+            Some(expected) if *expected == self.answer => "correct",
+            Some(_) => "wrong",
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        // numbers stay numbers, anything else becomes a string
+        let value = |s: &str| match s.parse::<i64>() {
+            Ok(n) => n.to_string(),
+            Err(_) => format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"")),
+        };
+        format!(
+            "{{\"day\":{},\"part\":{},\"answer\":{},\"expected\":{},\"status\":\"{}\",\"elapsed_us\":{}}}",
+            self.day,
+            self.part,
+            value(&self.answer),
+            self.expected.as_deref().map_or("null".to_string(), value),
+            self.status(),
+            self.elapsed.as_micros()
+        )
+    }
+}
+
+/// Check a part's answer and report it. Text output panics on a wrong answer and calls
+/// `text` to print the day's own line; JSON output prints the run and its status instead.
+/// An `expected` of `None` marks a day that isn't solved yet, which is never checked.
+/// `elapsed` runs from just after the input file is read, so it covers parsing the input for
+/// that part as well as solving it.
+pub fn report<T: Display + Debug + PartialEq>(
+    format: Format,
+    (day, part): (u32, u32),
+    answer: T,
+    expected: Option<T>,
+    elapsed: Duration,
+    text: impl FnOnce(),
+) {
+    match format {
+        Format::Text => {
+            if let Some(expected) = expected {
+                assert_eq!(answer, expected);
+            }
+            text();
+        }
+        Format::Json => {
+            let run = Run {
+                day,
+                part,
+                answer: answer.to_string(),
+                expected: expected.map(|e| e.to_string()),
+                elapsed,
+            };
+            println!("{}", run.to_json());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> impl Iterator<Item = String> + '_ {
+        s.split_whitespace().map(String::from)
+    }
+
+    #[test]
+    fn parse_works() {
+        let stats = vec!["--stats".to_string(), "3".to_string()];
+        assert_eq!(Format::parse(args(""), &[]), (Format::Text, vec![]));
+        assert_eq!(
+            Format::parse(args("--format json"), &[]),
+            (Format::Json, vec![])
+        );
+        assert_eq!(
+            Format::parse(args("--stats 3 --format text"), &[]),
+            (Format::Text, stats.clone())
+        );
+        assert_eq!(
+            Format::parse(args("--format json --stats 3"), &["--stats"]),
+            (Format::Json, stats)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "--format json can't be used with --trend")]
+    fn json_is_only_for_answers() {
+        Format::parse(args("--format json --stats 3 --trend"), &["--stats"]);
+    }
+
+    #[test]
+    fn to_json_works() {
+        let mut run = Run {
+            day: 2,
+            part: 1,
+            answer: "1840243".to_string(),
+            expected: Some("1840243".to_string()),
+            elapsed: Duration::from_micros(1500),
+        };
+        assert_eq!(
+            run.to_json(),
+            r#"{"day":2,"part":1,"answer":1840243,"expected":1840243,"status":"correct","elapsed_us":1500}"#
+        );
+        run.answer = "a\"b".to_string();
+        assert_eq!(run.status(), "wrong");
+        assert!(run
+            .to_json()
+            .contains(r#""answer":"a\"b","expected":1840243,"status":"wrong""#));
+        run.expected = None;
+        assert!(run
+            .to_json()
+            .contains(r#""expected":null,"status":"unknown""#));
+    }
+}